@@ -0,0 +1,243 @@
+// Simulates producers pushing StockInformation json to the input port and consumers
+// subscribed to "*" on the output port, then reports throughput, drops and latency.
+//
+// cargo run --release --bin load_generator -- --producers 4 --consumers 100 --symbols 500 --rate 1000 --duration 30
+//
+// Producers send interval 5 bars with "t" in unix millis like any other producer. The send time in
+// unix micros travels in "lg_sent", a field the datastore passes through, consumers measure the latency
+// against it. Only bars sent during this run are counted.
+//
+// The bars are stored like real ones under the symbols LG00000, LG00001 and so on. They stay in the
+// cache, the snapshot, the write ahead log and the history archive, run it against a scratch datastore.
+
+use std::env;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures_util::{SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+const LOAD_INTERVAL: usize = 5;
+// Producers send one update per tick of at least a microsecond
+const MAX_RATE: u64 = 1_000_000;
+
+struct LoadConfig {
+    url_in: String,
+    url_out: String,
+    producers: usize,
+    consumers: usize,
+    symbols: usize,
+    rate: u64,
+    duration: Duration,
+}
+
+impl LoadConfig {
+    fn from_args() -> Self {
+        let mut config = LoadConfig {
+            url_in: "ws://localhost:9003".to_string(),
+            url_out: "ws://localhost:9004".to_string(),
+            producers: 1,
+            consumers: 1,
+            symbols: 100,
+            rate: 1000,
+            duration: Duration::from_secs(10),
+        };
+
+        let args:Vec<String> = env::args().skip(1).collect();
+
+        for pair in args.chunks(2) {
+            let (key, value) = match pair {
+                [key, value] => (key.as_str(), value.as_str()),
+                _ => panic!("Missing value for {}", pair[0]),
+            };
+
+            match key {
+                "--in" => config.url_in = value.to_string(),
+                "--out" => config.url_out = value.to_string(),
+                "--producers" => config.producers = value.parse::<usize>().unwrap(),
+                "--consumers" => config.consumers = value.parse::<usize>().unwrap(),
+                "--symbols" => config.symbols = value.parse::<usize>().unwrap().max(1),
+                "--rate" => config.rate = match value.parse::<u64>().unwrap() {
+                    v if v > MAX_RATE => panic!("Rate {} is above the maximum of {} updates/s per producer", v, MAX_RATE),
+                    v => v.max(1),
+                },
+                "--duration" => config.duration = Duration::from_secs(value.parse::<u64>().unwrap()),
+                _ => panic!("Unknown argument {}", key),
+            }
+        }
+
+        config
+    }
+}
+
+// The fields of a received update the consumers look at
+#[derive(Deserialize)]
+struct LoadUpdate {
+    sn: String,
+    si: usize,
+    lg_sent: Option<u64>,
+}
+
+#[derive(Default)]
+struct ConsumerStats {
+    received: u64,
+    latencies: Vec<u64>,
+}
+
+#[tokio::main]
+async fn main() {
+    let config = Arc::new(LoadConfig::from_args());
+
+    println!("{} producers, {} consumers, {} symbols, {} updates/s per producer for {:?}",
+        config.producers, config.consumers, config.symbols, config.rate, config.duration);
+
+    let run_start:u64 = unix_micros();
+    let running = Arc::new(AtomicBool::new(true));
+    let sent = Arc::new(AtomicU64::new(0));
+    let stats = Arc::new(Mutex::new(ConsumerStats::default()));
+
+    let mut consumer_tasks = Vec::new();
+
+    for _ in 0..config.consumers {
+        consumer_tasks.push(tokio::spawn(run_consumer(config.clone(), running.clone(), stats.clone(), run_start)));
+    }
+
+    // Give the consumers time to subscribe before anything is sent
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let start = Instant::now();
+    let mut producer_tasks = Vec::new();
+
+    for producer_id in 0..config.producers {
+        producer_tasks.push(tokio::spawn(run_producer(config.clone(), producer_id, sent.clone())));
+    }
+
+    for task in producer_tasks.into_iter() {
+        let _ = task.await;
+    }
+
+    let send_time:Duration = start.elapsed();
+
+    // Let the datastore drain its queues
+    tokio::time::sleep(Duration::from_secs(2)).await;
+    running.store(false, Ordering::Relaxed);
+
+    for task in consumer_tasks.into_iter() {
+        let _ = task.await;
+    }
+
+    let total_sent:u64 = sent.load(Ordering::Relaxed);
+    let mut stats = stats.lock().await;
+    let expected:u64 = total_sent * config.consumers as u64;
+
+    println!("Sent      {} updates ({:.0}/s)", total_sent, total_sent as f64 / send_time.as_secs_f64());
+    println!("Received  {} updates ({:.0}/s)", stats.received, stats.received as f64 / send_time.as_secs_f64());
+    println!("Dropped   {} of {} expected", expected.saturating_sub(stats.received), expected);
+
+    stats.latencies.sort_unstable();
+
+    for (name, percentile) in [("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("p99.9", 0.999), ("max", 1.0)] {
+        match percentile_of(&stats.latencies, percentile) {
+            Some(v) => println!("Latency {:>5} {:>8} us", name, v),
+            None => println!("Latency {:>5} n/a", name),
+        }
+    }
+}
+
+async fn run_producer(config: Arc<LoadConfig>, producer_id: usize, sent: Arc<AtomicU64>) {
+    let (mut websocket, _) = match connect_async(config.url_in.as_str()).await {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Producer {} couldn't connect: {}", producer_id, e);
+
+            return;
+        },
+    };
+
+    let start = Instant::now();
+    let mut ticker = tokio::time::interval(Duration::from_micros(1_000_000 / config.rate));
+    let mut counter:usize = 0;
+
+    while start.elapsed() < config.duration {
+        ticker.tick().await;
+
+        let symbol:usize = (producer_id + counter * config.producers.max(1)) % config.symbols;
+        let sent_at:u64 = unix_micros();
+        let update = format!("{{\"sn\":\"LG{:05}\",\"si\":{},\"t\":{},\"ap\":{}.25,\"op\":1.0,\"mn\":1.0,\"mx\":2.0,\"vm\":1,\"nt\":1,\"lg_sent\":{}}}",
+            symbol, LOAD_INTERVAL, sent_at / 1000, counter % 100, sent_at);
+
+        if websocket.send(Message::Text(update.into())).await.is_err() {
+            println!("Producer {} lost its connection", producer_id);
+
+            break;
+        }
+
+        sent.fetch_add(1, Ordering::Relaxed);
+        counter += 1;
+    }
+
+    let _ = websocket.close(None).await;
+}
+
+async fn run_consumer(config: Arc<LoadConfig>, running: Arc<AtomicBool>, stats: Arc<Mutex<ConsumerStats>>, run_start: u64) {
+    let (mut websocket, _) = match connect_async(config.url_out.as_str()).await {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Consumer couldn't connect: {}", e);
+
+            return;
+        },
+    };
+
+    let _ = websocket.send(Message::Text("{\"stock\":\"*\"}".into())).await;
+
+    let mut local_stats = ConsumerStats::default();
+
+    while running.load(Ordering::Relaxed) {
+        let message = match tokio::time::timeout(Duration::from_millis(100), websocket.next()).await {
+            Ok(Some(Ok(Message::Text(v)))) => v,
+            Ok(Some(Ok(_))) | Err(_) => continue,
+            Ok(Some(Err(_))) | Ok(None) => break,
+        };
+
+        let received_at:u64 = unix_micros();
+
+        let update:LoadUpdate = match serde_json::from_str(&message) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+
+        if !update.sn.starts_with("LG") || update.si != LOAD_INTERVAL {
+            continue;
+        }
+
+        let sent_at:u64 = match update.lg_sent {
+            Some(v) if v >= run_start => v,
+            _ => continue,
+        };
+
+        local_stats.received += 1;
+        local_stats.latencies.push(received_at.saturating_sub(sent_at));
+    }
+
+    let mut stats = stats.lock().await;
+    stats.received += local_stats.received;
+    stats.latencies.extend(local_stats.latencies);
+}
+
+fn percentile_of(sorted: &[u64], percentile: f64) -> Option<u64> {
+    if sorted.is_empty() {
+        return None;
+    }
+
+    let index = ((sorted.len() - 1) as f64 * percentile).round() as usize;
+
+    Some(sorted[index])
+}
+
+fn unix_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64
+}
\ No newline at end of file
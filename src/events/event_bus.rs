@@ -0,0 +1,84 @@
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{SyncSender, TrySendError};
+
+use crate::events::update_event::UpdateEvent;
+use crate::value_store::stock_information_cache::StockInformation;
+use crate::websockets::connection_queue::SharedUpdate;
+
+// Consumers are called on the publishing thread, they must hand slow work off instead of blocking it
+pub trait EventConsumer: Send + Sync {
+    fn consume(&self, event: &UpdateEvent);
+}
+
+// Publishes update events from ingest to every consumer, in the order they subscribed
+pub struct EventBus {
+    consumers: RwLock<Vec<Arc<dyn EventConsumer>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        EventBus { consumers: RwLock::new(Vec::new()) }
+    }
+
+    pub fn subscribe(&self, consumer: Arc<dyn EventConsumer>) {
+        self.consumers.write().unwrap().push(consumer);
+    }
+
+    pub fn publish(&self, event: UpdateEvent) {
+        for consumer in self.consumers.read().unwrap().iter() {
+            consumer.consume(&event);
+        }
+    }
+}
+
+// Forwards the events it selects to a consumer thread. A full channel drops the event instead of blocking ingest
+pub struct ChannelConsumer<T> {
+    name: String,
+    sender: SyncSender<T>,
+    select: fn(&UpdateEvent) -> Option<T>,
+    dropped: AtomicUsize,
+}
+
+impl<T> ChannelConsumer<T> {
+    pub fn new(name: &str, sender: SyncSender<T>, select: fn(&UpdateEvent) -> Option<T>) -> Self {
+        ChannelConsumer { name: name.to_string(), sender, select, dropped: AtomicUsize::new(0) }
+    }
+}
+
+impl<T: Send> EventConsumer for ChannelConsumer<T> {
+    fn consume(&self, event: &UpdateEvent) {
+        let selected:T = match (self.select)(event) {
+            Some(v) => v,
+            None => return,
+        };
+
+        match self.sender.try_send(selected) {
+            Ok(_) => (),
+            Err(TrySendError::Full(_)) => {
+                let dropped:usize = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+
+                if dropped % 1000 == 1 {
+                    println!("Event consumer {} is full. Dropped {} events so far", self.name, dropped);
+                }
+            },
+            Err(TrySendError::Disconnected(_)) => (),
+        }
+    }
+}
+
+// Selects live updates for distribution to the subscribed clients
+pub fn live_update(event: &UpdateEvent) -> Option<(String, SharedUpdate, u64)> {
+    match event {
+        UpdateEvent::StockUpdated { stock_name, update, received_at } => Some((stock_name.clone(), update.clone(), *received_at)),
+        _ => None,
+    }
+}
+
+// Selects finalized bars for the persistence sinks
+pub fn finalized_bar(event: &UpdateEvent) -> Option<StockInformation> {
+    match event {
+        UpdateEvent::BarFinalized(stock_info) => Some(stock_info.clone()),
+        _ => None,
+    }
+}
\ No newline at end of file
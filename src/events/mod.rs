@@ -0,0 +1,2 @@
+pub mod event_bus;
+pub mod update_event;
\ No newline at end of file
@@ -0,0 +1,11 @@
+use crate::value_store::stock_information_cache::StockInformation;
+use crate::websockets::connection_queue::SharedUpdate;
+
+#[derive(Clone)]
+pub enum UpdateEvent {
+    // An update from a producer was applied to the cache. The update is the json as subscribers get it,
+    // received_at the unix micros the producer message arrived at
+    StockUpdated { stock_name: String, update: SharedUpdate, received_at: u64 },
+    // A bar was followed by a bar with a different timestamp of the same series, including during log replay
+    BarFinalized(StockInformation),
+}
\ No newline at end of file
@@ -0,0 +1,11 @@
+use std::path::{Path, PathBuf};
+
+// Clients name files inside a directory the configuration allows, only by their name.
+// Hidden files and anything with a path separator are rejected
+pub fn client_file_path(dir: &Path, file: &str) -> Result<PathBuf, String> {
+    if file.is_empty() || file.starts_with('.') || file.contains(['/', '\\']) {
+        return Err(format!("Invalid file name {}", file));
+    }
+
+    Ok(dir.join(file))
+}
\ No newline at end of file
@@ -0,0 +1,230 @@
+use std::fs;
+use std::thread;
+
+pub struct DatastoreConfig {
+    pub session_reset_offset: i64,
+    pub cache_shards: usize,
+    pub snapshot_path: String,
+    pub snapshot_interval_secs: u64,
+    pub wal_dir: String,
+    pub wal_segment_size: u64,
+    pub wal_fsync: bool,
+    pub postgres_url: String,
+    pub postgres_batch_size: usize,
+    pub postgres_flush_ms: u64,
+    pub postgres_buffer_size: usize,
+    pub postgres_max_retries: usize,
+    pub history_dir: String,
+    pub history_buffer_size: usize,
+    pub history_chunk_size: usize,
+    pub history_max_bars: usize,
+    pub ingest_workers: usize,
+    pub ingest_queue_size: usize,
+    pub latency_stamps: bool,
+    pub memory_budget: usize,
+    pub delta_keyframe_interval: usize,
+    pub client_queue_size: usize,
+    pub client_queue_disconnect: bool,
+    pub distribution_buffer_size: usize,
+    pub price_precision: u32,
+    pub symbol_price_precision: Vec<(String, u32)>,
+    pub load_csv: Vec<String>,
+    pub csv_columns: Vec<String>,
+    pub csv_header: bool,
+    pub csv_load_dir: String,
+    pub admin_address: String,
+    pub export_history: Vec<String>,
+    pub export_symbols: Vec<String>,
+    pub export_interval: Option<usize>,
+    pub export_from: Option<i64>,
+    pub export_to: Option<i64>,
+    pub export_dir: String,
+    pub feed_config: String,
+}
+
+impl DatastoreConfig {
+    pub fn new() -> Self {
+        DatastoreConfig {
+            session_reset_offset: 0,
+            cache_shards: 16,
+            snapshot_path: "StockDatastore.snapshot".to_string(),
+            snapshot_interval_secs: 30,
+            wal_dir: "wal".to_string(),
+            wal_segment_size: 64 * 1024 * 1024,
+            wal_fsync: false,
+            postgres_url: String::new(),
+            postgres_batch_size: 500,
+            postgres_flush_ms: 1000,
+            postgres_buffer_size: 100_000,
+            postgres_max_retries: 5,
+            history_dir: "history".to_string(),
+            history_buffer_size: 100_000,
+            history_chunk_size: 500,
+            history_max_bars: 100_000,
+            ingest_workers: thread::available_parallelism().map(|v| v.get()).unwrap_or(4),
+            ingest_queue_size: 10_000,
+            latency_stamps: false,
+            memory_budget: 1024 * 1024 * 1024,
+            delta_keyframe_interval: 30,
+            client_queue_size: 10_000,
+            client_queue_disconnect: false,
+            distribution_buffer_size: 100_000,
+            price_precision: 4,
+            symbol_price_precision: Vec::new(),
+            load_csv: Vec::new(),
+            csv_columns: parse_list("symbol,interval,timestamp,open,avg,min,max,volume,trades"),
+            csv_header: true,
+            csv_load_dir: String::new(),
+            admin_address: String::new(),
+            export_history: Vec::new(),
+            export_symbols: Vec::new(),
+            export_interval: None,
+            export_from: None,
+            export_to: None,
+            export_dir: String::new(),
+            feed_config: "Feeds.conf".to_string(),
+        }
+    }
+
+    fn insert_data(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "session_reset_utc" => self.session_reset_offset = parse_time_of_day(value)?,
+            "cache_shards" => self.cache_shards = parse_value::<usize>(key, value)?,
+            "snapshot_path" => self.snapshot_path = value.to_string(),
+            "snapshot_interval_secs" => self.snapshot_interval_secs = parse_value::<u64>(key, value)?,
+            "wal_dir" => self.wal_dir = value.to_string(),
+            "wal_segment_size" => self.wal_segment_size = parse_value::<u64>(key, value)?,
+            "wal_fsync" => self.wal_fsync = parse_value::<bool>(key, value)?,
+            "postgres_url" => self.postgres_url = value.to_string(),
+            "postgres_batch_size" => self.postgres_batch_size = parse_value::<usize>(key, value)?,
+            "postgres_flush_ms" => self.postgres_flush_ms = parse_value::<u64>(key, value)?,
+            "postgres_buffer_size" => self.postgres_buffer_size = parse_value::<usize>(key, value)?,
+            "postgres_max_retries" => self.postgres_max_retries = parse_value::<usize>(key, value)?,
+            "history_dir" => self.history_dir = value.to_string(),
+            "history_buffer_size" => self.history_buffer_size = parse_value::<usize>(key, value)?,
+            "history_chunk_size" => self.history_chunk_size = parse_value::<usize>(key, value)?,
+            "history_max_bars" => self.history_max_bars = parse_value::<usize>(key, value)?,
+            "ingest_workers" => self.ingest_workers = parse_value::<usize>(key, value)?,
+            "ingest_queue_size" => self.ingest_queue_size = parse_value::<usize>(key, value)?,
+            "latency_stamps" => self.latency_stamps = parse_value::<bool>(key, value)?,
+            "memory_budget" => self.memory_budget = parse_value::<usize>(key, value)?,
+            "delta_keyframe_interval" => self.delta_keyframe_interval = parse_value::<usize>(key, value)?,
+            "client_queue_size" => self.client_queue_size = parse_value::<usize>(key, value)?,
+            "client_queue_disconnect" => self.client_queue_disconnect = parse_value::<bool>(key, value)?,
+            "distribution_buffer_size" => self.distribution_buffer_size = parse_value::<usize>(key, value)?,
+            "price_precision" => self.price_precision = parse_value::<u32>(key, value)?,
+            "symbol_price_precision" => self.symbol_price_precision = parse_symbol_precision(value)?,
+            "load_csv" => self.load_csv = parse_list(value),
+            "csv_columns" => self.csv_columns = parse_list(value),
+            "csv_header" => self.csv_header = parse_value::<bool>(key, value)?,
+            "csv_load_dir" => self.csv_load_dir = value.to_string(),
+            "admin_address" => self.admin_address = value.to_string(),
+            "export_history" => self.export_history = parse_list(value),
+            "export_symbols" => self.export_symbols = parse_list(value),
+            "export_interval" => self.export_interval = parse_optional::<usize>(key, value)?,
+            "export_from" => self.export_from = parse_optional::<i64>(key, value)?,
+            "export_to" => self.export_to = parse_optional::<i64>(key, value)?,
+            "export_dir" => self.export_dir = value.to_string(),
+            "feed_config" => self.feed_config = value.to_string(),
+            _ => println!("Unknown config key {}", key),
+        }
+
+        Ok(())
+    }
+
+    // Command line arguments override the config file, e.g. --load-csv bars.csv sets load_csv
+    pub fn apply_args(&mut self, args: Vec<String>) -> Result<(), String> {
+        for pair in args.chunks(2) {
+            match pair {
+                [key, value] if key.starts_with("--") => self.insert_data(&key[2..].replace('-', "_"), value)?,
+                _ => return Err(format!("Invalid arguments {:?}, expected --key value", pair)),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct DatastoreConfigReader {
+    file: String,
+}
+
+impl DatastoreConfigReader {
+    pub fn new() -> Self {
+        DatastoreConfigReader{ file: "Datastore.conf".to_string() }
+    }
+
+    pub fn read_config(&self) -> Result<DatastoreConfig, String> {
+        let mut config = DatastoreConfig::new();
+
+        let data: String = match fs::read_to_string(&self.file) {
+            Ok(v) => v,
+            Err(_) => {
+                println!("No config file {} found. Using defaults", self.file);
+
+                return Ok(config);
+            },
+        };
+
+        for line in data.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') { continue; }
+
+            match line.split_once('=') {
+                Some((key, value)) => config.insert_data(key.trim(), value.trim())?,
+                None => println!("Invalid config line {}", line),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("Invalid value {} of {}", value, key))
+}
+
+// Converts "HH:MM" into milliseconds after midnight UTC
+fn parse_time_of_day(value: &str) -> Result<i64, String> {
+    let invalid = || format!("Invalid time of day {}, expected HH:MM", value);
+
+    let (hours, minutes) = match value.split_once(':') {
+        Some((h, m)) => (h.trim().parse::<i64>().map_err(|_| invalid())?, m.trim().parse::<i64>().map_err(|_| invalid())?),
+        None => return Err(invalid()),
+    };
+
+    if !(0..=23).contains(&hours) || !(0..=59).contains(&minutes) {
+        return Err(invalid());
+    }
+
+    Ok((hours * 60 + minutes) * 60_000)
+}
+
+// Comma separated values without the blanks
+fn parse_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(|entry| entry.trim().to_string())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+// Empty is None
+fn parse_optional<T: std::str::FromStr>(key: &str, value: &str) -> Result<Option<T>, String> {
+    match value.is_empty() {
+        true => Ok(None),
+        false => parse_value::<T>(key, value).map(Some),
+    }
+}
+
+// Converts "AAPL:2,EURUSD:5" into the precision of each symbol
+fn parse_symbol_precision(value: &str) -> Result<Vec<(String, u32)>, String> {
+    value.split(',')
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.rsplit_once(':') {
+            Some((stock_name, precision)) => Ok((stock_name.trim().to_string(), parse_value::<u32>("symbol_price_precision", precision.trim())?)),
+            None => Err(format!("Invalid symbol price precision {}", entry)),
+        })
+        .collect()
+}
\ No newline at end of file
@@ -0,0 +1,62 @@
+use std::fs;
+
+use crate::protocol::feed_mapping::FeedMapping;
+
+// Field mappings of foreign feeds, one [name] section per feed with the keys of FeedMapping
+pub struct FeedConfigReader {
+    file: String,
+}
+
+impl FeedConfigReader {
+    pub fn new(file: &str) -> Self {
+        FeedConfigReader{ file: file.to_string() }
+    }
+
+    pub fn read_config(&self) -> Result<Vec<FeedMapping>, String> {
+        let mut feed_mappings:Vec<FeedMapping> = Vec::new();
+
+        if self.file.is_empty() {
+            return Ok(feed_mappings);
+        }
+
+        let data: String = match fs::read_to_string(&self.file) {
+            Ok(v) => v,
+            Err(_) => {
+                println!("No feed config file {} found. No feeds are mapped", self.file);
+
+                return Ok(feed_mappings);
+            },
+        };
+
+        for line in data.lines() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') { continue; }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+                let name:&str = name.trim();
+
+                if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                    return Err(format!("Invalid feed name {}", name));
+                }
+
+                feed_mappings.push(FeedMapping::new(name));
+                continue;
+            }
+
+            match (line.split_once('='), feed_mappings.last_mut()) {
+                (Some((key, value)), Some(feed_mapping)) => feed_mapping.insert_data(key.trim(), value.trim())?,
+                (Some(_), None) => return Err(format!("Feed config line {} is outside of a [feed] section", line)),
+                (None, _) => println!("Invalid feed config line {}", line),
+            }
+        }
+
+        for feed_mapping in feed_mappings.iter() {
+            feed_mapping.validate()?;
+
+            println!("Mapped feed {}", feed_mapping.name());
+        }
+
+        Ok(feed_mappings)
+    }
+}
\ No newline at end of file
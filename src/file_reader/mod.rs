@@ -1 +1,4 @@
-pub mod stock_config_reader;
\ No newline at end of file
+pub mod stock_config_reader;
+pub mod datastore_config_reader;
+pub mod feed_config_reader;
+pub mod client_file;
\ No newline at end of file
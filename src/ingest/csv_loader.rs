@@ -0,0 +1,213 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::file_reader::client_file::client_file_path;
+use crate::persistence::history_archive::HistoryArchive;
+use crate::protocol::json_codec::json_string;
+use crate::value_store::price::Price;
+use crate::value_store::stock_information_cache::{StockInformation, StockInformationCache};
+
+const LOAD_BATCH_SIZE: usize = 10_000;
+const MAX_REPORTED_ERRORS: usize = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum CsvField {
+    Symbol,
+    Interval,
+    Timestamp,
+    Open,
+    Avg,
+    Min,
+    Max,
+    Volume,
+    Trades,
+    Skip,
+}
+
+impl CsvField {
+    fn from_name(name: &str) -> Self {
+        match name.trim().to_lowercase().as_str() {
+            "symbol" => CsvField::Symbol,
+            "interval" => CsvField::Interval,
+            "timestamp" => CsvField::Timestamp,
+            "open" => CsvField::Open,
+            "avg" => CsvField::Avg,
+            "min" => CsvField::Min,
+            "max" => CsvField::Max,
+            "volume" => CsvField::Volume,
+            "trades" => CsvField::Trades,
+            _ => CsvField::Skip,
+        }
+    }
+}
+
+pub struct CsvLoadReport {
+    pub loaded: usize,
+    pub skipped: usize,
+    pub errors: Vec<String>,
+}
+
+impl CsvLoadReport {
+    pub fn to_json(&self, file: &str) -> String {
+        let errors:Vec<String> = self.errors.iter().map(|e| json_string(e)).collect();
+
+        format!("{{\"stats\":\"load_csv\",\"file\":{},\"loaded\":{},\"skipped\":{},\"errors\":[{}]}}",
+            json_string(file), self.loaded, self.skipped, errors.join(","))
+    }
+}
+
+// Loads historical bars from csv files into the cache and the history archive, without sending them
+// to subscribers. columns names the field of every column in file order, columns named anything else
+// than symbol, interval, timestamp, open, avg, min, max, volume or trades are ignored
+pub struct CsvLoader {
+    columns: Vec<CsvField>,
+    has_header: bool,
+    load_dir: Option<PathBuf>,
+    stock_information_cache: Arc<StockInformationCache>,
+    history_archive: Arc<HistoryArchive>,
+}
+
+impl CsvLoader {
+    // Clients may only load files from load_dir, loading is disabled for them if it is empty
+    pub fn new(columns: &[String],
+               has_header: bool,
+               load_dir: &str,
+               stock_information_cache: Arc<StockInformationCache>,
+               history_archive: Arc<HistoryArchive>) -> Self {
+        CsvLoader {
+            columns: columns.iter().map(|name| CsvField::from_name(name)).collect(),
+            has_header,
+            load_dir: match load_dir.len() {
+                0 => None,
+                _ => Some(PathBuf::from(load_dir)),
+            },
+            stock_information_cache,
+            history_archive,
+        }
+    }
+
+    // For the load_csv command, the file name is taken relative to the load directory
+    pub fn load_client_file(&self, file: &str) -> Result<CsvLoadReport, String> {
+        let load_dir:&Path = match &self.load_dir {
+            Some(v) => v,
+            None => return Err("Loading csv files is disabled".to_string()),
+        };
+
+        self.load_file(&client_file_path(load_dir, file)?)
+    }
+
+    pub fn load_file(&self, path: &Path) -> Result<CsvLoadReport, String> {
+        let data:String = match fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Couldn't read {}: {}", path.display(), e)),
+        };
+
+        let mut report = CsvLoadReport { loaded: 0, skipped: 0, errors: Vec::new() };
+        let mut batch:Vec<StockInformation> = Vec::with_capacity(LOAD_BATCH_SIZE);
+        let skip_lines:usize = if self.has_header { 1 } else { 0 };
+
+        for (line_number, line) in data.lines().enumerate().skip(skip_lines) {
+            if line.trim().is_empty() { continue; }
+
+            match self.parse_line(line) {
+                Ok(v) => batch.push(v),
+                Err(e) => {
+                    report.skipped += 1;
+
+                    if report.errors.len() < MAX_REPORTED_ERRORS {
+                        report.errors.push(format!("Line {}: {}", line_number + 1, e));
+                    }
+                },
+            }
+
+            if batch.len() >= LOAD_BATCH_SIZE {
+                report.loaded += self.load_batch(std::mem::take(&mut batch))?;
+            }
+        }
+
+        report.loaded += self.load_batch(batch)?;
+
+        println!("Loaded {} bars from {}, skipped {} lines", report.loaded, path.display(), report.skipped);
+
+        Ok(report)
+    }
+
+    fn load_batch(&self, batch: Vec<StockInformation>) -> Result<usize, String> {
+        if batch.is_empty() {
+            return Ok(0);
+        }
+
+        let bars:Vec<StockInformation> = self.stock_information_cache.load_bars(batch);
+        let loaded:usize = bars.len();
+
+        match self.history_archive.write_bars(bars) {
+            Ok(_) => Ok(loaded),
+            Err(e) => Err(format!("Error writing history archive: {}", e)),
+        }
+    }
+
+    fn parse_line(&self, line: &str) -> Result<StockInformation, String> {
+        let values:Vec<String> = split_line(line);
+        let mut stock_info = StockInformation::new();
+        let mut has_timestamp:bool = false;
+
+        for (field, value) in self.columns.iter().zip(values.iter()) {
+            let value:&str = value.trim();
+
+            match field {
+                CsvField::Symbol => stock_info.stock_name = value.to_string(),
+                CsvField::Interval => stock_info.stock_interval = parse_number(value, "interval")?,
+                CsvField::Timestamp => {
+                    stock_info.timestamp = parse_number(value, "timestamp")?;
+                    has_timestamp = true;
+                },
+                CsvField::Open => stock_info.avg_price_open = Price::parse(value)?,
+                CsvField::Avg => stock_info.avg_price = Price::parse(value)?,
+                CsvField::Min => stock_info.min_price = Price::parse(value)?,
+                CsvField::Max => stock_info.max_price = Price::parse(value)?,
+                CsvField::Volume => stock_info.volume_moved = parse_number(value, "volume")?,
+                CsvField::Trades => stock_info.num_of_trades = parse_number(value, "trades")?,
+                CsvField::Skip => (),
+            }
+        }
+
+        if stock_info.stock_name.is_empty() {
+            return Err("Missing symbol".to_string());
+        }
+
+        if !has_timestamp {
+            return Err("Missing timestamp".to_string());
+        }
+
+        Ok(stock_info)
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, name: &str) -> Result<T, String> {
+    value.parse::<T>().map_err(|_| format!("Invalid {} {}", name, value))
+}
+
+// Fields may be quoted, "" inside quotes is a quote
+pub fn split_line(line: &str) -> Vec<String> {
+    let mut values:Vec<String> = Vec::new();
+    let mut value:String = String::new();
+    let mut in_quotes:bool = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, in_quotes) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                value.push('"');
+                chars.next();
+            },
+            ('"', _) => in_quotes = !in_quotes,
+            (',', false) => values.push(std::mem::take(&mut value)),
+            _ => value.push(c),
+        }
+    }
+
+    values.push(value);
+
+    values
+}
\ No newline at end of file
@@ -0,0 +1,94 @@
+use std::thread;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use crate::events::event_bus::EventBus;
+use crate::events::update_event::UpdateEvent;
+use crate::persistence::write_ahead_log::WriteAheadLog;
+use crate::value_store::cache_shard::append_json_fields;
+use crate::value_store::cache_view::shard_index;
+use crate::value_store::stock_information_cache::{StockInformation, StockInformationCache};
+use crate::websockets::connection_queue::SharedUpdate;
+
+// Every symbol is pinned to one worker, so updates of a symbol are applied in the order they arrived
+pub struct IngestPipeline {
+    workers: Vec<Sender<IngestUpdate>>,
+}
+
+// A decoded update, its json as the producer sent it and the unix micros it arrived at
+struct IngestUpdate {
+    stock_info: StockInformation,
+    json_data: String,
+    received_at: u64,
+}
+
+impl IngestPipeline {
+    pub fn start(num_of_workers: usize,
+                 queue_size: usize,
+                 event_bus: Arc<EventBus>,
+                 stock_information_cache: Arc<StockInformationCache>,
+                 write_ahead_log: Arc<Mutex<WriteAheadLog>>,
+                 latency_stamps: bool) -> Self {
+        let mut workers:Vec<Sender<IngestUpdate>> = Vec::new();
+
+        for worker_id in 0..num_of_workers.max(1) {
+            let (sender, receiver) = mpsc::channel::<IngestUpdate>(queue_size.max(1));
+
+            let event_bus = event_bus.clone();
+            let stock_information_cache = stock_information_cache.clone();
+            let write_ahead_log = write_ahead_log.clone();
+
+            thread::spawn(move || run_worker(
+                worker_id, receiver, event_bus,
+                stock_information_cache, write_ahead_log, latency_stamps
+            ));
+
+            workers.push(sender);
+        }
+
+        IngestPipeline { workers }
+    }
+
+    // Waits while the worker of the symbol is busy, which slows the producer down instead of dropping updates.
+    // received_at is the unix micros the producer message arrived at
+    pub async fn submit(&self, stock_info: StockInformation, json_data: String, received_at: u64) {
+        let worker:usize = shard_index(&stock_info.stock_name, self.workers.len());
+        let update = IngestUpdate { stock_info, json_data, received_at };
+
+        if self.workers[worker].send(update).await.is_err() {
+            println!("Ingest worker {} stopped. Dropping update", worker);
+        }
+    }
+}
+
+fn run_worker(worker_id: usize,
+              mut receiver: Receiver<IngestUpdate>,
+              event_bus: Arc<EventBus>,
+              stock_information_cache: Arc<StockInformationCache>,
+              write_ahead_log: Arc<Mutex<WriteAheadLog>>,
+              latency_stamps: bool) {
+    while let Some(IngestUpdate { stock_info, json_data, received_at }) = receiver.blocking_recv() {
+        let update = stock_information_cache.add_logged_json(stock_info, &json_data, |json_data| {
+            write_ahead_log.lock().unwrap().append(json_data)
+        });
+
+        let (name, text) = match update {
+            Ok(v) => v,
+            Err(e) => {
+                println!("Error writing to write ahead log {}. Dropping update", e);
+
+                continue;
+            },
+        };
+
+        let text:String = match latency_stamps {
+            true => append_json_fields(&text, &format!("\"rt\":{}", received_at)),
+            false => text,
+        };
+
+        event_bus.publish(UpdateEvent::StockUpdated { stock_name: name, update: SharedUpdate::from(text), received_at });
+    }
+
+    println!("Closing ingest worker {}", worker_id);
+}
\ No newline at end of file
@@ -0,0 +1,2 @@
+pub mod csv_loader;
+pub mod ingest_pipeline;
\ No newline at end of file
@@ -1,13 +1,45 @@
 mod value_store;
+mod events;
 mod file_reader;
+mod ingest;
+mod metrics;
+mod persistence;
+mod protocol;
 mod websockets;
 
+use std::env;
+use std::process;
+
 use crate::websockets::websocket_server::WebSocketServer;
 use crate::file_reader::stock_config_reader::StockConfigReader;
+use crate::file_reader::datastore_config_reader::DatastoreConfigReader;
+use crate::file_reader::feed_config_reader::FeedConfigReader;
 
-fn main() {
+#[tokio::main]
+async fn main() {
     let stock_list:Vec<String> = StockConfigReader::new().read_config();
+    let config = DatastoreConfigReader::new().read_config().and_then(|mut config| {
+        config.apply_args(env::args().skip(1).collect())?;
+
+        Ok(config)
+    });
+
+    let config = match config {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Invalid configuration: {}", e);
+            process::exit(1);
+        },
+    };
+
+    let feed_mappings = match FeedConfigReader::new(&config.feed_config).read_config() {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Invalid feed configuration: {}", e);
+            process::exit(1);
+        },
+    };
     
-    let websocket_server = WebSocketServer::new("localhost:9003", "localhost:9004", stock_list);
-    websocket_server.start_server();
+    let websocket_server = WebSocketServer::new("localhost:9003", "localhost:9004", stock_list, config, feed_mappings);
+    websocket_server.start_server().await;
 }
@@ -0,0 +1,33 @@
+use crate::metrics::latency_histogram::LatencyHistogram;
+use crate::websockets::connection_queue::QueuedUpdate;
+
+// Latencies of the live updates sent to one client: from the producer message arriving until the
+// update was queued for the client, and from being queued until it was written to the socket
+pub struct ClientLatency {
+    ingest_to_queue: LatencyHistogram,
+    queue_to_socket: LatencyHistogram,
+}
+
+impl ClientLatency {
+    pub fn new() -> Self {
+        ClientLatency { ingest_to_queue: LatencyHistogram::new(), queue_to_socket: LatencyHistogram::new() }
+    }
+
+    // Cache dumps and history replies have no receive time and aren't counted
+    pub fn record(&mut self, updates: &[QueuedUpdate], sent_at: u64) {
+        for update in updates.iter() {
+            let received_at:u64 = match update.received_at {
+                Some(v) => v,
+                None => continue,
+            };
+
+            self.ingest_to_queue.record(update.queued_at.saturating_sub(received_at));
+            self.queue_to_socket.record(sent_at.saturating_sub(update.queued_at));
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!("{{\"stats\":\"latency\",\"ingest_to_queue\":{},\"queue_to_socket\":{}}}",
+            self.ingest_to_queue.to_json(), self.queue_to_socket.to_json())
+    }
+}
\ No newline at end of file
@@ -0,0 +1,36 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::events::event_bus::EventConsumer;
+use crate::events::update_event::UpdateEvent;
+
+// Counts what ingest published since the datastore started
+pub struct IngestMetrics {
+    updates: AtomicU64,
+    finalized_bars: AtomicU64,
+    last_received_at: AtomicU64,
+}
+
+impl IngestMetrics {
+    pub fn new() -> Self {
+        IngestMetrics { updates: AtomicU64::new(0), finalized_bars: AtomicU64::new(0), last_received_at: AtomicU64::new(0) }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!("{{\"stats\":\"ingest\",\"updates\":{},\"finalized_bars\":{},\"last_received_at\":{}}}",
+            self.updates.load(Ordering::Relaxed),
+            self.finalized_bars.load(Ordering::Relaxed),
+            self.last_received_at.load(Ordering::Relaxed))
+    }
+}
+
+impl EventConsumer for IngestMetrics {
+    fn consume(&self, event: &UpdateEvent) {
+        match event {
+            UpdateEvent::StockUpdated { received_at, .. } => {
+                self.updates.fetch_add(1, Ordering::Relaxed);
+                self.last_received_at.fetch_max(*received_at, Ordering::Relaxed);
+            },
+            UpdateEvent::BarFinalized(_) => { self.finalized_bars.fetch_add(1, Ordering::Relaxed); },
+        }
+    }
+}
\ No newline at end of file
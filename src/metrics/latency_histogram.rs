@@ -0,0 +1,84 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LINEAR_BUCKETS: usize = 16;
+const SUB_BUCKETS: usize = 8;
+const NUM_OF_BUCKETS: usize = LINEAR_BUCKETS + (64 - 4) * SUB_BUCKETS;
+
+// Log linear histogram of latencies in microseconds, every bucket is at most 12.5% wide
+pub struct LatencyHistogram {
+    buckets: Vec<u64>,
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl LatencyHistogram {
+    pub fn new() -> Self {
+        LatencyHistogram { buckets: vec![0; NUM_OF_BUCKETS], count: 0, sum: 0, max: 0 }
+    }
+
+    pub fn record(&mut self, micros: u64) {
+        self.buckets[bucket_index(micros)] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(micros);
+        self.max = self.max.max(micros);
+    }
+
+    // Upper bound of the bucket holding the percentile, capped at the largest recorded value
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+
+        let rank:u64 = ((self.count as f64 * percentile).ceil() as u64).max(1);
+        let mut seen:u64 = 0;
+
+        for (index, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket;
+
+            if seen >= rank {
+                return bucket_upper_bound(index).min(self.max);
+            }
+        }
+
+        self.max
+    }
+
+    pub fn to_json(&self) -> String {
+        let mean:u64 = match self.count {
+            0 => 0,
+            v => self.sum / v,
+        };
+
+        format!("{{\"count\":{},\"mean\":{},\"p50\":{},\"p90\":{},\"p99\":{},\"p999\":{},\"max\":{}}}",
+            self.count, mean,
+            self.percentile(0.5), self.percentile(0.9), self.percentile(0.99), self.percentile(0.999),
+            self.max)
+    }
+}
+
+fn bucket_index(micros: u64) -> usize {
+    if micros < LINEAR_BUCKETS as u64 {
+        return micros as usize;
+    }
+
+    let exponent = 63 - micros.leading_zeros() as usize;
+    let sub_bucket = ((micros >> (exponent - 3)) & (SUB_BUCKETS as u64 - 1)) as usize;
+
+    LINEAR_BUCKETS + (exponent - 4) * SUB_BUCKETS + sub_bucket
+}
+
+fn bucket_upper_bound(index: usize) -> u64 {
+    if index < LINEAR_BUCKETS {
+        return index as u64;
+    }
+
+    let exponent = (index - LINEAR_BUCKETS) / SUB_BUCKETS + 4;
+    let sub_bucket = ((index - LINEAR_BUCKETS) % SUB_BUCKETS) as u64;
+
+    ((SUB_BUCKETS as u64 + sub_bucket + 1) << (exponent - 3)).saturating_sub(1)
+}
+
+pub fn unix_micros() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64
+}
\ No newline at end of file
@@ -0,0 +1,3 @@
+pub mod client_latency;
+pub mod ingest_metrics;
+pub mod latency_histogram;
\ No newline at end of file
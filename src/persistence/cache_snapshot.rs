@@ -0,0 +1,208 @@
+use std::thread;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::persistence::write_ahead_log::WriteAheadLog;
+use crate::value_store::stock_information_cache::StockInformationCache;
+
+pub struct CacheSnapshot {
+    snapshot_path: PathBuf,
+    snapshot_interval: Duration,
+    stock_information_cache: Arc<StockInformationCache>,
+    write_ahead_log: Arc<Mutex<WriteAheadLog>>,
+}
+
+impl CacheSnapshot {
+    pub fn new(snapshot_path: &str,
+               snapshot_interval: Duration,
+               stock_information_cache: Arc<StockInformationCache>,
+               write_ahead_log: Arc<Mutex<WriteAheadLog>>) -> Self {
+        CacheSnapshot {
+            snapshot_path: PathBuf::from(snapshot_path),
+            snapshot_interval,
+            stock_information_cache,
+            write_ahead_log,
+        }
+    }
+
+    pub fn restore(&self) {
+        let snapshot:String = match fs::read_to_string(&self.snapshot_path) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("No snapshot restored from {:?}: {}", self.snapshot_path, e);
+
+                return;
+            },
+        };
+
+        match self.stock_information_cache.restore_snapshot(&snapshot) {
+            Ok(v) => println!("Restored {} entries from snapshot {:?}", v, self.snapshot_path),
+            Err(e) => println!("Couldn't restore snapshot {:?}: {}", self.snapshot_path, e),
+        }
+
+        let watermark:u64 = self.stock_information_cache.view().log_watermark();
+
+        match self.write_ahead_log.lock().unwrap().continue_after(watermark) {
+            Ok(_) => (),
+            Err(e) => panic!("Couldn't continue write ahead log after sequence {}: {}", watermark, e),
+        }
+    }
+
+    // Applies every logged update the snapshot doesn't contain yet
+    pub fn replay_log(&self) {
+        let mut applied:usize = 0;
+
+        let replayed = self.write_ahead_log.lock().unwrap().replay(0, |sequence, json_data| {
+            if self.stock_information_cache.replay_json(json_data, sequence) {
+                applied += 1;
+            }
+        });
+
+        match replayed {
+            Ok(v) => println!("Replayed {} of {} updates from write ahead log", applied, v),
+            Err(e) => panic!("Couldn't replay write ahead log: {}", e),
+        }
+    }
+
+    pub fn write_snapshot(&self) -> io::Result<()> {
+        let (snapshot, sequence) = self.stock_information_cache.to_snapshot(|| {
+            self.write_ahead_log.lock().unwrap().last_sequence()
+        });
+
+        write_atomic(&self.snapshot_path, snapshot.as_bytes())?;
+
+        match self.write_ahead_log.lock().unwrap().compact(sequence)? {
+            0 => (),
+            v => println!("Removed {} write ahead log segments covered by snapshot", v),
+        }
+
+        Ok(())
+    }
+
+    pub fn start_snapshots(self) {
+        thread::spawn(move || {
+            loop {
+                thread::sleep(self.snapshot_interval);
+
+                match self.write_snapshot() {
+                    Ok(_) => (),
+                    Err(e) => println!("Error writing snapshot {:?}: {}", self.snapshot_path, e),
+                }
+            }
+        });
+    }
+}
+
+// Writes to a temporary file first so a crash never leaves a half written snapshot behind
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp_path:PathBuf = path.with_extension("tmp");
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+
+    fs::rename(&tmp_path, path)?;
+
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => File::open(dir)?.sync_all(),
+        _ => File::open(".")?.sync_all(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::event_bus::EventBus;
+    use crate::protocol::json_codec::decode_stock_info;
+    use crate::value_store::price::TickPrecision;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir:PathBuf = std::env::temp_dir().join(format!("snapshot-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn cache_snapshot(dir: &Path) -> CacheSnapshot {
+        let write_ahead_log = WriteAheadLog::open(dir.join("wal").to_str().unwrap(), 1024, false).unwrap();
+
+        CacheSnapshot::new(
+            dir.join("cache.snapshot").to_str().unwrap(),
+            Duration::from_secs(60),
+            Arc::new(StockInformationCache::new(0, 4, 0, Vec::new(), TickPrecision::new(2, Vec::new()), Arc::new(EventBus::new()))),
+            Arc::new(Mutex::new(write_ahead_log))
+        )
+    }
+
+    fn add(cache_snapshot: &CacheSnapshot, json_data: &str) {
+        cache_snapshot.stock_information_cache
+            .add_logged_json(decode_stock_info(json_data).unwrap(), json_data, |v| cache_snapshot.write_ahead_log.lock().unwrap().append(v))
+            .unwrap();
+    }
+
+    fn sorted(mut lines: Vec<String>) -> Vec<String> {
+        lines.sort();
+
+        lines
+    }
+
+    #[test]
+    fn restores_what_was_written() {
+        let dir:PathBuf = test_dir("round-trip");
+        let written = cache_snapshot(&dir);
+
+        add(&written, "{\"sn\":\"AAPL\",\"si\":1,\"t\":1000,\"ap\":187.20,\"op\":187.1,\"mn\":187.1,\"mx\":187.3,\"vm\":100,\"nt\":3}");
+        add(&written, "{\"sn\":\"AAPL\",\"si\":1,\"t\":2000,\"ap\":187.25,\"op\":187.2,\"mn\":187.2,\"mx\":187.3,\"vm\":50,\"nt\":1}");
+        add(&written, "{\"sn\":\"AAPL\",\"si\":60,\"t\":0,\"ap\":187.22,\"op\":187.1,\"mn\":187.1,\"mx\":187.3,\"vm\":150,\"nt\":4}");
+        add(&written, "{\"sn\":\"MSFT\",\"si\":60,\"t\":0,\"ap\":410.5,\"op\":410,\"mn\":410,\"mx\":411,\"vm\":10,\"nt\":1}");
+
+        written.write_snapshot().unwrap();
+
+        let snapshot:String = fs::read_to_string(dir.join("cache.snapshot")).unwrap();
+        let restored = cache_snapshot(&dir);
+        restored.restore();
+
+        let (restored_snapshot, sequence) = restored.stock_information_cache.to_snapshot(|| restored.write_ahead_log.lock().unwrap().last_sequence());
+
+        assert_eq!(sequence, 4);
+        assert_eq!(sorted(restored_snapshot.lines().map(String::from).collect()), sorted(snapshot.lines().map(String::from).collect()));
+        assert_eq!(sorted(restored.stock_information_cache.get_entire_cache()), sorted(written.stock_information_cache.get_entire_cache()));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn log_continues_after_the_snapshot() {
+        let dir:PathBuf = test_dir("emptied-log");
+        let written = cache_snapshot(&dir);
+
+        for timestamp in [0, 60_000, 120_000] {
+            add(&written, &format!("{{\"sn\":\"AAPL\",\"si\":60,\"t\":{},\"ap\":187.2,\"vm\":1}}", timestamp));
+        }
+
+        written.write_snapshot().unwrap();
+        drop(written);
+
+        fs::remove_dir_all(dir.join("wal")).unwrap();
+
+        let restored = cache_snapshot(&dir);
+        restored.restore();
+        add(&restored, "{\"sn\":\"AAPL\",\"si\":60,\"t\":180000,\"ap\":187.3,\"vm\":1}");
+
+        assert_eq!(restored.write_ahead_log.lock().unwrap().last_sequence(), 4);
+
+        drop(restored);
+
+        let replayed = cache_snapshot(&dir);
+        replayed.restore();
+        replayed.replay_log();
+
+        assert_eq!(replayed.stock_information_cache.view().get_history_range("AAPL", 60, 0, i64::MAX).len(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,267 @@
+use std::thread;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::Entry;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+
+use crate::value_store::cache_shard::stored_timestamp;
+use crate::value_store::stock_information_cache::StockInformation;
+
+const DAY_LENGTH: i64 = 86_400_000;
+const MAX_BATCH_SIZE: usize = 1000;
+
+// Finalized bars stored as one json line per bar in history/<stock>/<interval>/<day>.seg
+pub struct HistoryArchive {
+    history_dir: PathBuf,
+}
+
+impl HistoryArchive {
+    pub fn new(history_dir: &str) -> Self {
+        HistoryArchive { history_dir: PathBuf::from(history_dir) }
+    }
+
+    // Returns the bounded buffer finalized bars are written into
+    pub fn start(&self, buffer_size: usize) -> SyncSender<StockInformation> {
+        let (sender, receiver) = mpsc::sync_channel::<StockInformation>(buffer_size);
+        let history_dir = self.history_dir.clone();
+
+        thread::spawn(move || run_writer(history_dir, receiver));
+
+        sender
+    }
+
+    // Writes bars right away instead of through the buffer, e.g. bars loaded in bulk
+    pub fn write_bars(&self, bars: Vec<StockInformation>) -> io::Result<()> {
+        write_batch(&self.history_dir, bars, &mut HashMap::new())
+    }
+
+    // The oldest bars of the range, at most limit of them. A bar written more than once is returned as last written
+    pub fn read_range(&self, stock_name: &str, interval: usize, from: i64, to: i64, limit: usize) -> io::Result<Vec<(i64, String)>> {
+        let mut bars:Vec<(i64, String)> = Vec::new();
+
+        if !is_valid_stock_name(stock_name) || from > to {
+            return Ok(bars);
+        }
+
+        let (first_day, last_day) = (from.div_euclid(DAY_LENGTH), to.div_euclid(DAY_LENGTH));
+
+        let mut days:Vec<i64> = list_segment_days(&self.history_dir, stock_name, interval)?;
+        days.sort_unstable();
+
+        for day in days.into_iter() {
+            if day < first_day || day > last_day { continue; }
+
+            // Later days only hold newer bars
+            if bars.len() >= limit { break; }
+
+            let data:String = match fs::read_to_string(segment_path(&self.history_dir, stock_name, interval, day)) {
+                Ok(v) => v,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            for json_data in data.lines() {
+                let timestamp:i64 = stored_timestamp(json_data);
+
+                if timestamp < from || timestamp > to { continue; }
+
+                bars.push((timestamp, json_data.to_string()));
+            }
+        }
+
+        bars.sort_by_key(|(timestamp, _)| *timestamp);
+        bars.reverse();
+        bars.dedup_by_key(|(timestamp, _)| *timestamp);
+        bars.reverse();
+        bars.truncate(limit);
+
+        Ok(bars)
+    }
+}
+
+fn run_writer(history_dir: PathBuf, receiver: Receiver<StockInformation>) {
+    let mut newest:HashMap<(String, usize), Option<i64>> = HashMap::new();
+
+    while let Ok(stock_info) = receiver.recv() {
+        let mut batch:Vec<StockInformation> = vec![stock_info];
+
+        while batch.len() < MAX_BATCH_SIZE {
+            match receiver.try_recv() {
+                Ok(v) => batch.push(v),
+                Err(_) => break,
+            }
+        }
+
+        match write_batch(&history_dir, batch, &mut newest) {
+            Ok(_) => (),
+            Err(e) => println!("Error writing history archive: {}", e),
+        }
+    }
+
+    println!("Closing history archive");
+}
+
+// Bars finalized again by the write ahead log replay after a restart were mostly archived before it. They are
+// skipped if their segment holds the same line already, a late bar with other values is still appended.
+// newest holds the newest archived timestamp per series, only bars up to it are looked up in their segment
+fn write_batch(history_dir: &Path, batch: Vec<StockInformation>, newest: &mut HashMap<(String, usize), Option<i64>>) -> io::Result<()> {
+    let mut segments:HashMap<PathBuf, String> = HashMap::new();
+    let mut archived:HashMap<PathBuf, HashSet<String>> = HashMap::new();
+
+    for stock_info in batch.into_iter() {
+        if !is_valid_stock_name(&stock_info.stock_name) {
+            println!("Not archiving bar with invalid stock name {:?}", stock_info.stock_name);
+
+            continue;
+        }
+
+        let day:i64 = stock_info.timestamp.div_euclid(DAY_LENGTH);
+        let path = segment_path(history_dir, &stock_info.stock_name, stock_info.stock_interval, day);
+        let json_data:String = stock_info.to_json();
+
+        let series_newest = match newest.entry((stock_info.stock_name.clone(), stock_info.stock_interval)) {
+            Entry::Occupied(v) => v.into_mut(),
+            Entry::Vacant(v) => v.insert(newest_timestamp(history_dir, &stock_info.stock_name, stock_info.stock_interval)?),
+        };
+
+        if series_newest.is_some_and(|v| stock_info.timestamp <= v) {
+            let lines = match archived.entry(path.clone()) {
+                Entry::Occupied(v) => v.into_mut(),
+                Entry::Vacant(v) => v.insert(read_lines(&path)?),
+            };
+
+            if !lines.insert(json_data.clone()) { continue; }
+        }
+
+        *series_newest = Some(series_newest.map_or(stock_info.timestamp, |v| v.max(stock_info.timestamp)));
+
+        let lines = segments.entry(path).or_default();
+        lines.push_str(&json_data);
+        lines.push('\n');
+    }
+
+    for (path, lines) in segments.into_iter() {
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        OpenOptions::new().create(true).append(true).open(&path)?.write_all(lines.as_bytes())?;
+    }
+
+    Ok(())
+}
+
+// Days of the segments stored for a series, a range of years costs no more than the files that exist
+fn list_segment_days(history_dir: &Path, stock_name: &str, interval: usize) -> io::Result<Vec<i64>> {
+    let entries = match fs::read_dir(history_dir.join(stock_name).join(interval.to_string())) {
+        Ok(v) => v,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut days:Vec<i64> = Vec::new();
+
+    for entry in entries {
+        let file_name = entry?.file_name();
+
+        if let Some(day) = file_name.to_str().and_then(|v| v.strip_suffix(".seg")).and_then(|v| v.parse::<i64>().ok()) {
+            days.push(day);
+        }
+    }
+
+    Ok(days)
+}
+
+// Newest timestamp in the newest segment of a series
+fn newest_timestamp(history_dir: &Path, stock_name: &str, interval: usize) -> io::Result<Option<i64>> {
+    let day:i64 = match list_segment_days(history_dir, stock_name, interval)?.into_iter().max() {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    Ok(read_lines(&segment_path(history_dir, stock_name, interval, day))?.iter().map(|json_data| stored_timestamp(json_data)).max())
+}
+
+fn read_lines(path: &Path) -> io::Result<HashSet<String>> {
+    match fs::read_to_string(path) {
+        Ok(v) => Ok(v.lines().map(|json_data| json_data.to_string()).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e),
+    }
+}
+
+fn segment_path(history_dir: &Path, stock_name: &str, interval: usize, day: i64) -> PathBuf {
+    history_dir.join(stock_name).join(interval.to_string()).join(format!("{}.seg", day))
+}
+
+// Stock names end up in file paths
+fn is_valid_stock_name(stock_name: &str) -> bool {
+    !stock_name.is_empty()
+        && !stock_name.starts_with('.')
+        && stock_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir:PathBuf = std::env::temp_dir().join(format!("history-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    fn bar(timestamp: i64, volume_moved: i64) -> StockInformation {
+        StockInformation { stock_name: "AAPL".to_string(), stock_interval: 60, timestamp, volume_moved, ..StockInformation::new() }
+    }
+
+    fn volumes(archive: &HistoryArchive, limit: usize) -> Vec<(i64, i64)> {
+        archive.read_range("AAPL", 60, 0, i64::MAX, limit).unwrap().into_iter()
+            .map(|(timestamp, json_data)| (timestamp, serde_json::from_str::<StockInformation>(&json_data).unwrap().volume_moved))
+            .collect()
+    }
+
+    #[test]
+    fn keeps_the_last_written_bar() {
+        let dir:PathBuf = test_dir("last-written");
+        let archive = HistoryArchive::new(dir.to_str().unwrap());
+
+        write_batch(&dir, vec![bar(60_000, 1), bar(120_000, 2)], &mut HashMap::new()).unwrap();
+        write_batch(&dir, vec![bar(60_000, 3)], &mut HashMap::new()).unwrap();
+
+        assert_eq!(volumes(&archive, 10), vec![(60_000, 3), (120_000, 2)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn skips_bars_archived_already() {
+        let dir:PathBuf = test_dir("replayed");
+        let archive = HistoryArchive::new(dir.to_str().unwrap());
+
+        write_batch(&dir, vec![bar(60_000, 1), bar(120_000, 2)], &mut HashMap::new()).unwrap();
+        write_batch(&dir, vec![bar(60_000, 1), bar(120_000, 2), bar(180_000, 4)], &mut HashMap::new()).unwrap();
+
+        let data:String = fs::read_to_string(segment_path(&dir, "AAPL", 60, 0)).unwrap();
+
+        assert_eq!(data.lines().count(), 3);
+        assert_eq!(volumes(&archive, 10), vec![(60_000, 1), (120_000, 2), (180_000, 4)]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn returns_the_oldest_bars_up_to_the_limit() {
+        let dir:PathBuf = test_dir("limit");
+        let archive = HistoryArchive::new(dir.to_str().unwrap());
+
+        write_batch(&dir, (0..5).map(|day| bar(day * DAY_LENGTH, day)).collect(), &mut HashMap::new()).unwrap();
+
+        assert_eq!(volumes(&archive, 2), vec![(0, 0), (DAY_LENGTH, 1)]);
+        assert_eq!(volumes(&archive, 10).len(), 5);
+        assert!(archive.read_range("AAPL", 60, 0, i64::MAX, 0).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
\ No newline at end of file
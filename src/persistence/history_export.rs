@@ -0,0 +1,288 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType, FixedLenByteArray, FixedLenByteArrayType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+
+use crate::file_reader::client_file::client_file_path;
+use crate::protocol::json_codec::decode_stock_info;
+use crate::value_store::price::Price;
+use crate::value_store::stock_information_cache::{StockInformation, StockInformationCache};
+
+const PARQUET_ROW_GROUP_SIZE: usize = 100_000;
+
+// Price columns are 16 byte decimals, any i64 price at any scale up to MAX_PRICE_SCALE fits
+const PARQUET_PRICE_PRECISION: u32 = 38;
+
+const PRICE_COLUMNS: [&str; 5] = ["avg_price", "avg_price_open", "min_price", "max_price", "session_vwap"];
+
+// Named like the StockInformation fields, in file order
+const COLUMNS: [&str; 12] = [
+    "stock_name",
+    "stock_interval",
+    "timestamp",
+    "avg_price",
+    "avg_price_open",
+    "min_price",
+    "max_price",
+    "volume_moved",
+    "num_of_trades",
+    "session_vwap",
+    "session_volume",
+    "session_trades",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExportFormat {
+    Csv,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        match path.extension().and_then(|v| v.to_str()).map(|v| v.to_lowercase()).as_deref() {
+            Some("csv") => Ok(ExportFormat::Csv),
+            Some("parquet") => Ok(ExportFormat::Parquet),
+            _ => Err(format!("Unknown export format of {}, expected .csv or .parquet", path.display())),
+        }
+    }
+}
+
+// Which bars are exported, no symbols means every symbol and no interval every interval
+#[derive(Clone, Debug)]
+pub struct ExportFilter {
+    pub symbols: Vec<String>,
+    pub interval: Option<usize>,
+    pub from: i64,
+    pub to: i64,
+}
+
+impl ExportFilter {
+    pub fn new(symbols: Vec<String>, interval: Option<usize>, from: Option<i64>, to: Option<i64>) -> Self {
+        ExportFilter {
+            symbols,
+            interval,
+            from: from.unwrap_or(i64::MIN),
+            to: to.unwrap_or(i64::MAX),
+        }
+    }
+
+    fn matches(&self, stock_name: &str, interval: usize) -> bool {
+        (self.symbols.is_empty() || self.symbols.iter().any(|v| v == stock_name))
+            && self.interval.is_none_or(|v| v == interval)
+    }
+}
+
+// Dumps the history held in memory to csv or parquet files for offline analysis, the format follows
+// the file extension. Bars are sorted by symbol, interval and timestamp
+pub struct HistoryExporter {
+    export_dir: Option<PathBuf>,
+    stock_information_cache: Arc<StockInformationCache>,
+}
+
+impl HistoryExporter {
+    // Clients may only export to export_dir, exporting is disabled for them if it is empty
+    pub fn new(export_dir: &str, stock_information_cache: Arc<StockInformationCache>) -> Self {
+        HistoryExporter {
+            export_dir: match export_dir.len() {
+                0 => None,
+                _ => Some(PathBuf::from(export_dir)),
+            },
+            stock_information_cache,
+        }
+    }
+
+    // For the export_history command, the file name is taken relative to the export directory.
+    // Clients never overwrite a file that is already there
+    pub fn export_client_file(&self, file: &str, filter: &ExportFilter) -> Result<usize, String> {
+        let export_dir:&Path = match &self.export_dir {
+            Some(v) => v,
+            None => return Err("Exporting history is disabled".to_string()),
+        };
+
+        self.export_file(&client_file_path(export_dir, file)?, filter, false)
+    }
+
+    // Returns the number of exported bars
+    pub fn export_file(&self, path: &Path, filter: &ExportFilter, overwrite: bool) -> Result<usize, String> {
+        let format = ExportFormat::from_path(path)?;
+
+        let file:File = match create_file(path, overwrite) {
+            Ok(v) => v,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(format!("{} exists already", path.display())),
+            Err(e) => return Err(format!("Couldn't create {}: {}", path.display(), e)),
+        };
+
+        let bars:Vec<StockInformation> = self.collect_bars(filter);
+
+        let result = match format {
+            ExportFormat::Csv => write_csv(file, &bars),
+            ExportFormat::Parquet => write_parquet(file, &bars),
+        };
+
+        match result {
+            Ok(_) => {
+                println!("Exported {} bars to {}", bars.len(), path.display());
+
+                Ok(bars.len())
+            },
+            Err(e) => Err(format!("Couldn't write {}: {}", path.display(), e)),
+        }
+    }
+
+    fn collect_bars(&self, filter: &ExportFilter) -> Vec<StockInformation> {
+        let cache_view = self.stock_information_cache.view();
+
+        let mut series:Vec<(String, usize)> = cache_view.get_series().into_iter()
+            .filter(|(stock_name, interval)| filter.matches(stock_name, *interval))
+            .collect();
+
+        series.sort();
+
+        let mut bars:Vec<StockInformation> = Vec::new();
+
+        for (stock_name, interval) in series.iter() {
+            for (_, json_data) in cache_view.get_history_range(stock_name, *interval, filter.from, filter.to) {
+                match decode_stock_info(&json_data) {
+                    Ok(v) => bars.push(v),
+                    Err(e) => println!("Skipped bar of {} in export: {}", stock_name, e),
+                }
+            }
+        }
+
+        bars
+    }
+}
+
+fn create_file(path: &Path, overwrite: bool) -> io::Result<File> {
+    match overwrite {
+        true => File::create(path),
+        false => OpenOptions::new().write(true).create_new(true).open(path),
+    }
+}
+
+fn write_csv(file: File, bars: &[StockInformation]) -> Result<(), String> {
+    let mut writer = BufWriter::new(file);
+
+    writeln!(writer, "{}", COLUMNS.join(",")).map_err(|e| e.to_string())?;
+
+    for bar in bars.iter() {
+        writeln!(writer, "{},{},{},{},{},{},{},{},{},{},{},{}",
+            csv_value(&bar.stock_name), bar.stock_interval, bar.timestamp,
+            bar.avg_price, bar.avg_price_open, bar.min_price, bar.max_price,
+            bar.volume_moved, bar.num_of_trades,
+            bar.session_vwap, bar.session_volume, bar.session_trades).map_err(|e| e.to_string())?;
+    }
+
+    writer.flush().map_err(|e| e.to_string())
+}
+
+// Quoted if it holds a separator, a quote or a line break
+fn csv_value(value: &str) -> String {
+    match value.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string(),
+    }
+}
+
+// Prices are decimals with the largest scale of the exported prices, so every price stays exact
+fn write_parquet(file: File, bars: &[StockInformation]) -> Result<(), String> {
+    let price_scale:u32 = bars.iter()
+        .flat_map(|bar| [bar.avg_price, bar.avg_price_open, bar.min_price, bar.max_price, bar.session_vwap])
+        .map(|price| price.scale())
+        .max()
+        .unwrap_or(0);
+
+    let schema = parse_message_type(&parquet_schema(price_scale)).map_err(|e| e.to_string())?;
+    let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+
+    let mut writer = SerializedFileWriter::new(file, Arc::new(schema), Arc::new(properties)).map_err(|e| e.to_string())?;
+
+    for row_group_bars in bars.chunks(PARQUET_ROW_GROUP_SIZE) {
+        let mut row_group = writer.next_row_group().map_err(|e| e.to_string())?;
+        let mut index:usize = 0;
+
+        while let Some(mut column) = row_group.next_column().map_err(|e| e.to_string())? {
+            match COLUMNS[index] {
+                "stock_name" => {
+                    let values:Vec<ByteArray> = row_group_bars.iter().map(|bar| ByteArray::from(bar.stock_name.as_str())).collect();
+
+                    column.typed::<ByteArrayType>().write_batch(&values, None, None).map_err(|e| e.to_string())?;
+                },
+                name if PRICE_COLUMNS.contains(&name) => {
+                    let values:Vec<FixedLenByteArray> = row_group_bars.iter()
+                        .map(|bar| price_value(bar, name, price_scale))
+                        .collect::<Result<Vec<FixedLenByteArray>, String>>()?;
+
+                    column.typed::<FixedLenByteArrayType>().write_batch(&values, None, None).map_err(|e| e.to_string())?;
+                },
+                name => {
+                    let values:Vec<i64> = row_group_bars.iter()
+                        .map(|bar| int64_value(bar, name))
+                        .collect::<Result<Vec<i64>, String>>()?;
+
+                    column.typed::<Int64Type>().write_batch(&values, None, None).map_err(|e| e.to_string())?;
+                },
+            }
+
+            column.close().map_err(|e| e.to_string())?;
+            index += 1;
+        }
+
+        row_group.close().map_err(|e| e.to_string())?;
+    }
+
+    writer.close().map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+fn parquet_schema(price_scale: u32) -> String {
+    let fields:Vec<String> = COLUMNS.iter()
+        .map(|name| match *name {
+            "stock_name" => format!("required binary {} (UTF8);", name),
+            name if PRICE_COLUMNS.contains(&name) => {
+                format!("required fixed_len_byte_array(16) {} (DECIMAL({},{}));", name, PARQUET_PRICE_PRECISION, price_scale)
+            },
+            _ => format!("required int64 {};", name),
+        })
+        .collect();
+
+    format!("message stock_information {{ {} }}", fields.join(" "))
+}
+
+fn int64_value(bar: &StockInformation, name: &str) -> Result<i64, String> {
+    match name {
+        "stock_interval" => Ok(bar.stock_interval as i64),
+        "timestamp" => Ok(bar.timestamp),
+        "volume_moved" => Ok(bar.volume_moved),
+        "num_of_trades" => Ok(bar.num_of_trades),
+        "session_volume" => Ok(bar.session_volume),
+        "session_trades" => Ok(bar.session_trades),
+        _ => Err(format!("Unknown column {}", name)),
+    }
+}
+
+fn price_value(bar: &StockInformation, name: &str, price_scale: u32) -> Result<FixedLenByteArray, String> {
+    match name {
+        "avg_price" => price_bytes(bar.avg_price, price_scale),
+        "avg_price_open" => price_bytes(bar.avg_price_open, price_scale),
+        "min_price" => price_bytes(bar.min_price, price_scale),
+        "max_price" => price_bytes(bar.max_price, price_scale),
+        "session_vwap" => price_bytes(bar.session_vwap, price_scale),
+        _ => Err(format!("Unknown price column {}", name)),
+    }
+}
+
+// Big endian two's complement units at the column scale
+fn price_bytes(price: Price, price_scale: u32) -> Result<FixedLenByteArray, String> {
+    match price.units_at(price_scale) {
+        Some(units) => Ok(FixedLenByteArray::from(units.to_be_bytes().to_vec())),
+        None => Err(format!("Price {} doesn't fit a decimal with scale {}", price, price_scale)),
+    }
+}
\ No newline at end of file
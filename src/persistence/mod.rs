@@ -0,0 +1,5 @@
+pub mod cache_snapshot;
+pub mod history_archive;
+pub mod history_export;
+pub mod postgres_sink;
+pub mod write_ahead_log;
\ No newline at end of file
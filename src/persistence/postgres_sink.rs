@@ -0,0 +1,250 @@
+use std::thread;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::time::{Duration, Instant};
+
+use postgres::{Client, NoTls};
+
+use crate::value_store::stock_information_cache::StockInformation;
+
+const CREATE_TABLE: &str = "CREATE TABLE IF NOT EXISTS stock_bars (
+    stock_name TEXT NOT NULL,
+    stock_interval BIGINT NOT NULL,
+    timestamp BIGINT NOT NULL,
+    avg_price NUMERIC NOT NULL,
+    avg_price_open NUMERIC NOT NULL,
+    min_price NUMERIC NOT NULL,
+    max_price NUMERIC NOT NULL,
+    volume_moved BIGINT NOT NULL,
+    num_of_trades BIGINT NOT NULL,
+    PRIMARY KEY (stock_name, stock_interval, timestamp)
+)";
+
+// Tables created before prices were exact hold them as DOUBLE PRECISION, they are converted once
+const MIGRATE_PRICES: &str = "DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM information_schema.columns
+               WHERE table_schema = current_schema() AND table_name = 'stock_bars'
+               AND column_name = 'avg_price' AND data_type = 'double precision') THEN
+        ALTER TABLE stock_bars
+            ALTER COLUMN avg_price TYPE NUMERIC USING avg_price::NUMERIC,
+            ALTER COLUMN avg_price_open TYPE NUMERIC USING avg_price_open::NUMERIC,
+            ALTER COLUMN min_price TYPE NUMERIC USING min_price::NUMERIC,
+            ALTER COLUMN max_price TYPE NUMERIC USING max_price::NUMERIC;
+    END IF;
+END $$";
+
+// Bars replayed from the write ahead log after a restart may already be stored.
+// Prices are sent as their exact decimal text
+const INSERT_BAR: &str = "INSERT INTO stock_bars
+    (stock_name, stock_interval, timestamp, avg_price, avg_price_open, min_price, max_price, volume_moved, num_of_trades)
+    VALUES ($1, $2, $3, $4::TEXT::NUMERIC, $5::TEXT::NUMERIC, $6::TEXT::NUMERIC, $7::TEXT::NUMERIC, $8, $9)
+    ON CONFLICT DO NOTHING";
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+pub struct PostgresSink {
+    connection_string: String,
+    batch_size: usize,
+    flush_interval: Duration,
+    max_retries: usize,
+    client: Option<Client>,
+}
+
+impl PostgresSink {
+    pub fn new(connection_string: &str, batch_size: usize, flush_interval: Duration, max_retries: usize) -> Self {
+        PostgresSink {
+            connection_string: connection_string.to_string(),
+            batch_size,
+            flush_interval,
+            max_retries,
+            client: None,
+        }
+    }
+
+    // Returns the bounded buffer finalized bars are written into
+    pub fn start(self, buffer_size: usize) -> SyncSender<StockInformation> {
+        let (sender, receiver) = mpsc::sync_channel::<StockInformation>(buffer_size);
+
+        thread::spawn(move || self.run(receiver));
+
+        sender
+    }
+
+    fn run(mut self, receiver: Receiver<StockInformation>) {
+        let mut batch:Vec<StockInformation> = Vec::with_capacity(self.batch_size);
+        let mut last_flush = Instant::now();
+
+        loop {
+            let timeout = self.flush_interval.saturating_sub(last_flush.elapsed());
+
+            match receiver.recv_timeout(timeout) {
+                Ok(stock_info) => batch.push(stock_info),
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.write_batch(&batch);
+
+                    break;
+                },
+            }
+
+            if batch.len() < self.batch_size && last_flush.elapsed() < self.flush_interval {
+                continue;
+            }
+
+            if !batch.is_empty() {
+                self.write_batch(&batch);
+                batch.clear();
+            }
+
+            last_flush = Instant::now();
+        }
+
+        println!("Closing Postgres sink");
+    }
+
+    fn write_batch(&mut self, batch: &[StockInformation]) {
+        let mut retry_delay = Duration::from_millis(100);
+
+        for attempt in 0..=self.max_retries {
+            match self.try_write_batch(batch) {
+                Ok(_) => return,
+                Err(e) => {
+                    println!("Error writing {} bars to Postgres (attempt {}): {}", batch.len(), attempt + 1, e);
+
+                    self.client = None;
+                },
+            }
+
+            if attempt < self.max_retries {
+                thread::sleep(retry_delay);
+                retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+            }
+        }
+
+        println!("Dropping {} bars after {} retries", batch.len(), self.max_retries);
+    }
+
+    fn try_write_batch(&mut self, batch: &[StockInformation]) -> Result<(), postgres::Error> {
+        if self.client.is_none() {
+            let mut client = Client::connect(&self.connection_string, NoTls)?;
+            client.batch_execute(CREATE_TABLE)?;
+            client.batch_execute(MIGRATE_PRICES)?;
+
+            self.client = Some(client);
+        }
+
+        let client = self.client.as_mut().unwrap();
+        let mut transaction = client.transaction()?;
+        let statement = transaction.prepare(INSERT_BAR)?;
+
+        for stock_info in batch.iter() {
+            transaction.execute(&statement, &[
+                &stock_info.stock_name,
+                &(stock_info.stock_interval as i64),
+                &stock_info.timestamp,
+                &stock_info.avg_price.to_string(),
+                &stock_info.avg_price_open.to_string(),
+                &stock_info.min_price.to_string(),
+                &stock_info.max_price.to_string(),
+                &stock_info.volume_moved,
+                &stock_info.num_of_trades,
+            ])?;
+        }
+
+        transaction.commit()
+    }
+}
+
+// Needs a running Postgres, docker compose up postgresql-main starts the one of docker-compose.yml.
+// Run with cargo test -- --ignored, POSTGRES_TEST_URL overrides the connection string
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::value_store::price::Price;
+
+    fn connection_string() -> String {
+        std::env::var("POSTGRES_TEST_URL")
+            .unwrap_or_else(|_| "host=localhost user=postgres password=postgres dbname=postgres".to_string())
+    }
+
+    fn test_batch(stock_name: &str) -> Vec<StockInformation> {
+        (0..3)
+            .map(|i| StockInformation {
+                stock_name: stock_name.to_string(),
+                stock_interval: 60,
+                timestamp: 1_700_000_000 + i * 60,
+                avg_price: Price::parse("187.2301").unwrap(),
+                avg_price_open: Price::parse("187.1").unwrap(),
+                min_price: Price::parse("187.05").unwrap(),
+                max_price: Price::parse("187.4").unwrap(),
+                volume_moved: 4110 + i,
+                num_of_trades: 22,
+                ..StockInformation::new()
+            })
+            .collect()
+    }
+
+    #[test]
+    #[ignore]
+    fn writes_a_batch() {
+        let connection_string:String = connection_string();
+        let stock_name:String = format!("PGTEST{}", std::process::id());
+        let batch:Vec<StockInformation> = test_batch(&stock_name);
+
+        let mut sink = PostgresSink::new(&connection_string, batch.len(), Duration::from_secs(1), 0);
+        sink.write_batch(&batch);
+        // Written again, as after a replay of the write ahead log
+        sink.write_batch(&batch);
+
+        let mut client = Client::connect(&connection_string, NoTls).unwrap();
+        let rows = client.query(
+            "SELECT timestamp, avg_price::TEXT, min_price::TEXT, volume_moved FROM stock_bars WHERE stock_name = $1 ORDER BY timestamp",
+            &[&stock_name]
+        ).unwrap();
+        client.execute("DELETE FROM stock_bars WHERE stock_name = $1", &[&stock_name]).unwrap();
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].get::<_, i64>(0), 1_700_000_000);
+        assert_eq!(rows[0].get::<_, String>(1), "187.2301");
+        assert_eq!(rows[0].get::<_, String>(2), "187.05");
+        assert_eq!(rows[2].get::<_, i64>(3), 4112);
+    }
+
+    #[test]
+    #[ignore]
+    fn migrates_double_precision_prices() {
+        let schema:String = format!("pgtest_{}", std::process::id());
+        let mut client = Client::connect(&connection_string(), NoTls).unwrap();
+
+        client.batch_execute(&format!("DROP SCHEMA IF EXISTS {0} CASCADE; CREATE SCHEMA {0};
+            CREATE TABLE {0}.stock_bars (
+                stock_name TEXT NOT NULL,
+                stock_interval BIGINT NOT NULL,
+                timestamp BIGINT NOT NULL,
+                avg_price DOUBLE PRECISION NOT NULL,
+                avg_price_open DOUBLE PRECISION NOT NULL,
+                min_price DOUBLE PRECISION NOT NULL,
+                max_price DOUBLE PRECISION NOT NULL,
+                volume_moved BIGINT NOT NULL,
+                num_of_trades BIGINT NOT NULL,
+                PRIMARY KEY (stock_name, stock_interval, timestamp)
+            );
+            INSERT INTO {0}.stock_bars VALUES ('OLD', 60, 0, 187.23, 187.1, 187.05, 187.4, 10, 1);", schema)).unwrap();
+
+        let mut sink = PostgresSink::new(&format!("{} options='-c search_path={}'", connection_string(), schema), 3, Duration::from_secs(1), 0);
+        sink.write_batch(&test_batch("NEW"));
+
+        let types = client.query(
+            "SELECT data_type FROM information_schema.columns WHERE table_schema = $1 AND column_name LIKE '%price%'",
+            &[&schema]
+        ).unwrap();
+        let rows = client.query(&format!("SELECT stock_name, avg_price::TEXT FROM {}.stock_bars ORDER BY timestamp", schema), &[]).unwrap();
+        client.batch_execute(&format!("DROP SCHEMA {} CASCADE", schema)).unwrap();
+
+        assert_eq!(types.len(), 4);
+        assert!(types.iter().all(|row| row.get::<_, String>(0) == "numeric"));
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0].get::<_, String>(1), "187.23");
+        assert_eq!(rows[1].get::<_, String>(1), "187.2301");
+    }
+}
\ No newline at end of file
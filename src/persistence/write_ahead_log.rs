@@ -0,0 +1,374 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+const RECORD_HEADER_SIZE: usize = 16;
+const SEGMENT_EXTENSION: &str = "wal";
+
+// Record layout: payload length (u32), crc32 of sequence and payload (u32), sequence (u64), payload
+pub struct WriteAheadLog {
+    log_dir: PathBuf,
+    segment_size: u64,
+    sync_writes: bool,
+    next_sequence: u64,
+    segment: File,
+    segment_written: u64,
+}
+
+impl WriteAheadLog {
+    pub fn open(log_dir: &str, segment_size: u64, sync_writes: bool) -> io::Result<Self> {
+        let log_dir = PathBuf::from(log_dir);
+
+        fs::create_dir_all(&log_dir)?;
+
+        let segments:Vec<(u64, PathBuf)> = list_segments(&log_dir)?;
+
+        let (segment_path, next_sequence) = match segments.last() {
+            Some((first_sequence, path)) => {
+                let (records, valid_len) = read_segment(path)?;
+
+                // A torn record at the end is a write that never completed, drop it
+                if valid_len < fs::metadata(path)?.len() {
+                    println!("Truncating torn write ahead log record in {:?}", path);
+                    OpenOptions::new().write(true).open(path)?.set_len(valid_len)?;
+                }
+
+                match records.last() {
+                    Some((sequence, _)) => (path.clone(), sequence + 1),
+                    None => (path.clone(), *first_sequence),
+                }
+            },
+            None => (segment_path(&log_dir, 1), 1),
+        };
+
+        let segment = OpenOptions::new().create(true).append(true).open(&segment_path)?;
+        let segment_written = segment.metadata()?.len();
+
+        Ok(WriteAheadLog {
+            log_dir,
+            segment_size,
+            sync_writes,
+            next_sequence,
+            segment,
+            segment_written,
+        })
+    }
+
+    pub fn append(&mut self, payload: &str) -> io::Result<u64> {
+        if self.segment_written >= self.segment_size {
+            self.roll_segment()?;
+        }
+
+        let sequence:u64 = self.next_sequence;
+        let record:Vec<u8> = encode_record(sequence, payload.as_bytes());
+
+        self.segment.write_all(&record)?;
+
+        if self.sync_writes {
+            self.segment.sync_data()?;
+        }
+
+        self.segment_written += record.len() as u64;
+        self.next_sequence += 1;
+
+        Ok(sequence)
+    }
+
+    pub fn last_sequence(&self) -> u64 {
+        self.next_sequence - 1
+    }
+
+    // Records after a restored snapshot need sequences above everything it contains, even if the log
+    // was emptied since. A new segment starts at the next sequence, so it is kept when reopened
+    pub fn continue_after(&mut self, sequence: u64) -> io::Result<()> {
+        if sequence < self.next_sequence {
+            return Ok(());
+        }
+
+        self.next_sequence = sequence + 1;
+
+        self.roll_segment()
+    }
+
+    // Calls on_record for every record with a sequence after from_sequence, in order. Only the last segment
+    // may end early, open truncates its torn tail. A bad record anywhere else is corruption and an error,
+    // replaying past it would skip updates
+    pub fn replay<F: FnMut(u64, &str)>(&self, from_sequence: u64, mut on_record: F) -> io::Result<usize> {
+        let segments:Vec<(u64, PathBuf)> = list_segments(&self.log_dir)?;
+        let mut replayed:usize = 0;
+
+        for (index, (_, path)) in segments.iter().enumerate() {
+            let (records, valid_len) = read_segment(path)?;
+
+            if index + 1 < segments.len() && valid_len < fs::metadata(path)?.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Corrupt write ahead log record in {:?} at offset {}", path, valid_len)
+                ));
+            }
+
+            for (sequence, payload) in records.into_iter() {
+                if sequence <= from_sequence { continue; }
+
+                on_record(sequence, &payload);
+                replayed += 1;
+            }
+        }
+
+        Ok(replayed)
+    }
+
+    // Removes segments that only hold records already contained in a snapshot
+    pub fn compact(&mut self, snapshot_sequence: u64) -> io::Result<usize> {
+        let segments:Vec<(u64, PathBuf)> = list_segments(&self.log_dir)?;
+        let mut removed:usize = 0;
+
+        for window in segments.windows(2) {
+            let (next_first_sequence, _) = window[1];
+
+            if next_first_sequence > snapshot_sequence + 1 { break; }
+
+            fs::remove_file(&window[0].1)?;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+
+    fn roll_segment(&mut self) -> io::Result<()> {
+        self.segment.sync_all()?;
+
+        self.segment = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(&self.log_dir, self.next_sequence))?;
+        self.segment_written = 0;
+
+        Ok(())
+    }
+}
+
+fn segment_path(log_dir: &Path, first_sequence: u64) -> PathBuf {
+    log_dir.join(format!("{:020}.{}", first_sequence, SEGMENT_EXTENSION))
+}
+
+fn list_segments(log_dir: &Path) -> io::Result<Vec<(u64, PathBuf)>> {
+    let mut segments:Vec<(u64, PathBuf)> = Vec::new();
+
+    for entry in fs::read_dir(log_dir)? {
+        let path:PathBuf = entry?.path();
+
+        if path.extension().and_then(|v| v.to_str()) != Some(SEGMENT_EXTENSION) { continue; }
+
+        match path.file_stem().and_then(|v| v.to_str()).and_then(|v| v.parse::<u64>().ok()) {
+            Some(first_sequence) => segments.push((first_sequence, path)),
+            None => println!("Ignoring unknown file in write ahead log {:?}", path),
+        }
+    }
+
+    segments.sort();
+
+    Ok(segments)
+}
+
+// Returns all valid records and the length of the segment up to the last valid record
+fn read_segment(path: &Path) -> io::Result<(Vec<(u64, String)>, u64)> {
+    let mut data:Vec<u8> = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    let mut records:Vec<(u64, String)> = Vec::new();
+    let mut offset:usize = 0;
+
+    while offset + RECORD_HEADER_SIZE <= data.len() {
+        let payload_len = u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap()) as usize;
+        let checksum = u32::from_le_bytes(data[offset + 4..offset + 8].try_into().unwrap());
+        let record_end = offset + RECORD_HEADER_SIZE + payload_len;
+
+        if record_end > data.len() { break; }
+
+        if crc32(&data[offset + 8..record_end]) != checksum {
+            println!("Checksum mismatch in {:?} at offset {}", path, offset);
+
+            break;
+        }
+
+        let sequence = u64::from_le_bytes(data[offset + 8..offset + 16].try_into().unwrap());
+        let payload = String::from_utf8_lossy(&data[offset + RECORD_HEADER_SIZE..record_end]).to_string();
+
+        records.push((sequence, payload));
+        offset = record_end;
+    }
+
+    Ok((records, offset as u64))
+}
+
+fn encode_record(sequence: u64, payload: &[u8]) -> Vec<u8> {
+    let mut body:Vec<u8> = Vec::with_capacity(8 + payload.len());
+    body.extend_from_slice(&sequence.to_le_bytes());
+    body.extend_from_slice(payload);
+
+    let mut record:Vec<u8> = Vec::with_capacity(8 + body.len());
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(&crc32(&body).to_le_bytes());
+    record.extend_from_slice(&body);
+
+    record
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc:u32 = 0xFFFF_FFFF;
+
+    for byte in data.iter() {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            crc = match crc & 1 {
+                1 => (crc >> 1) ^ 0xEDB8_8320,
+                _ => crc >> 1,
+            };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir:PathBuf = std::env::temp_dir().join(format!("wal-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    fn replayed(log: &WriteAheadLog, from_sequence: u64) -> Vec<(u64, String)> {
+        let mut records:Vec<(u64, String)> = Vec::new();
+        log.replay(from_sequence, |sequence, payload| records.push((sequence, payload.to_string()))).unwrap();
+
+        records
+    }
+
+    #[test]
+    fn record_format() {
+        let record:Vec<u8> = encode_record(7, b"{\"sn\":\"AAPL\"}");
+
+        assert_eq!(record.len(), RECORD_HEADER_SIZE + 13);
+        assert_eq!(u32::from_le_bytes(record[0..4].try_into().unwrap()), 13);
+        assert_eq!(u32::from_le_bytes(record[4..8].try_into().unwrap()), crc32(&record[8..]));
+        assert_eq!(u64::from_le_bytes(record[8..16].try_into().unwrap()), 7);
+        assert_eq!(&record[16..], b"{\"sn\":\"AAPL\"}");
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn torn_tail_is_truncated() {
+        let dir:PathBuf = test_dir("torn");
+        let mut log = WriteAheadLog::open(dir.to_str().unwrap(), 1024 * 1024, false).unwrap();
+
+        for payload in ["a", "b", "c"] {
+            log.append(payload).unwrap();
+        }
+
+        drop(log);
+
+        let path:PathBuf = segment_path(&dir, 1);
+        let valid_len:u64 = fs::metadata(&path).unwrap().len();
+        let torn:Vec<u8> = encode_record(4, b"torn");
+        OpenOptions::new().append(true).open(&path).unwrap().write_all(&torn[..torn.len() - 2]).unwrap();
+
+        let mut log = WriteAheadLog::open(dir.to_str().unwrap(), 1024 * 1024, false).unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().len(), valid_len);
+        assert_eq!(log.last_sequence(), 3);
+        assert_eq!(log.append("d").unwrap(), 4);
+        assert_eq!(replayed(&log, 0).into_iter().map(|(_, payload)| payload).collect::<Vec<String>>(), vec!["a", "b", "c", "d"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corruption_before_the_last_segment_is_an_error() {
+        let dir:PathBuf = test_dir("corrupt");
+        let mut log = WriteAheadLog::open(dir.to_str().unwrap(), 1, false).unwrap();
+
+        for payload in ["a", "b", "c"] {
+            log.append(payload).unwrap();
+        }
+
+        let path:PathBuf = segment_path(&dir, 1);
+        let mut data:Vec<u8> = fs::read(&path).unwrap();
+        let last:usize = data.len() - 1;
+        data[last] ^= 0xFF;
+        fs::write(&path, data).unwrap();
+
+        assert!(log.replay(0, |_, _| ()).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay_after_compaction() {
+        let dir:PathBuf = test_dir("compact");
+        let mut log = WriteAheadLog::open(dir.to_str().unwrap(), 1, false).unwrap();
+
+        for payload in ["a", "b", "c", "d", "e"] {
+            log.append(payload).unwrap();
+        }
+
+        assert_eq!(log.compact(3).unwrap(), 3);
+        assert_eq!(replayed(&log, 3), vec![(4, "d".to_string()), (5, "e".to_string())]);
+
+        drop(log);
+
+        let mut log = WriteAheadLog::open(dir.to_str().unwrap(), 1, false).unwrap();
+
+        assert_eq!(log.append("f").unwrap(), 6);
+        assert_eq!(replayed(&log, 0).into_iter().map(|(sequence, _)| sequence).collect::<Vec<u64>>(), vec![4, 5, 6]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn continues_after_a_snapshot() {
+        let dir:PathBuf = test_dir("continue");
+        let mut log = WriteAheadLog::open(dir.to_str().unwrap(), 32, false).unwrap();
+
+        log.append("a").unwrap();
+        log.continue_after(0).unwrap();
+
+        assert_eq!(log.append("b").unwrap(), 2);
+
+        log.continue_after(100).unwrap();
+
+        assert_eq!(log.last_sequence(), 100);
+
+        drop(log);
+
+        let mut log = WriteAheadLog::open(dir.to_str().unwrap(), 32, false).unwrap();
+
+        assert_eq!(log.append("c").unwrap(), 101);
+        assert_eq!(replayed(&log, 0).into_iter().map(|(sequence, _)| sequence).collect::<Vec<u64>>(), vec![1, 2, 101]);
+        assert_eq!(log.compact(100).unwrap(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay_starts_after_the_watermark() {
+        let dir:PathBuf = test_dir("watermark");
+        let mut log = WriteAheadLog::open(dir.to_str().unwrap(), 32, false).unwrap();
+
+        for payload in ["a", "b", "c", "d"] {
+            log.append(payload).unwrap();
+        }
+
+        assert_eq!(replayed(&log, 2), vec![(3, "c".to_string()), (4, "d".to_string())]);
+        assert_eq!(replayed(&log, 4), Vec::new());
+        assert_eq!(log.compact(1).unwrap(), 0);
+        assert_eq!(replayed(&log, 0).len(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
\ No newline at end of file
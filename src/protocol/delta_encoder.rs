@@ -0,0 +1,134 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::value::RawValue;
+
+use crate::protocol::json_codec::json_string;
+
+type RawFields = BTreeMap<String, Box<RawValue>>;
+
+// Remembers per series what one client got last. Updates that only change a few fields are sent as
+// {"sn":..,"si":..,"d":1,<changed fields>}, every keyframe_interval updates the full update is sent again.
+// A delta can't remove fields, an update without a field of the last one is sent in full too.
+// Fields are compared and sent as the text they arrived in, so prices stay exact
+pub struct DeltaEncoder {
+    keyframe_interval: usize,
+    last_sent: HashMap<(String, u64), (RawFields, usize)>,
+}
+
+impl DeltaEncoder {
+    pub fn new(keyframe_interval: usize) -> Self {
+        DeltaEncoder { keyframe_interval: keyframe_interval.max(1), last_sent: HashMap::new() }
+    }
+
+    // Whatever the client gets next is sent in full, e.g. after a snapshot it didn't see deltas against
+    pub fn reset(&mut self) {
+        self.last_sent.clear();
+    }
+
+    // None means the full update has to be sent
+    pub fn encode(&mut self, json_data: &str) -> Option<String> {
+        let fields:RawFields = serde_json::from_str(json_data).ok()?;
+
+        let key:(String, u64) = match (raw_field::<String>(&fields, "sn"), raw_field::<u64>(&fields, "si")) {
+            (Some(stock_name), Some(interval)) => (stock_name, interval),
+            _ => return None,
+        };
+
+        let (last_fields, since_keyframe) = match self.last_sent.get_mut(&key) {
+            Some((last_fields, since_keyframe))
+                if *since_keyframe + 1 < self.keyframe_interval && last_fields.keys().all(|field| fields.contains_key(field)) => (last_fields, since_keyframe),
+            _ => {
+                self.last_sent.insert(key, (fields, 0));
+
+                return None;
+            },
+        };
+
+        let mut delta:String = format!("{{\"sn\":{},\"si\":{},\"d\":1", json_string(&key.0), key.1);
+
+        for (field, value) in fields.iter() {
+            if field == "sn" || field == "si" || last_fields.get(field).map(|v| v.get()) == Some(value.get()) {
+                continue;
+            }
+
+            delta.push_str(&format!(",{}:{}", json_string(field), value.get()));
+        }
+
+        delta.push('}');
+
+        *last_fields = fields;
+        *since_keyframe += 1;
+
+        Some(delta)
+    }
+}
+
+fn raw_field<T: serde::de::DeserializeOwned>(fields: &RawFields, key: &str) -> Option<T> {
+    fields.get(key).and_then(|value| serde_json::from_str(value.get()).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_changed_fields_only() {
+        let mut encoder = DeltaEncoder::new(10);
+
+        assert_eq!(encoder.encode("{\"sn\":\"AAPL\",\"si\":60,\"t\":1,\"ap\":187.20,\"vm\":5}"), None);
+        assert_eq!(encoder.encode("{\"sn\":\"AAPL\",\"si\":60,\"t\":1,\"ap\":187.20,\"vm\":7}").as_deref(),
+            Some("{\"sn\":\"AAPL\",\"si\":60,\"d\":1,\"vm\":7}"));
+        assert_eq!(encoder.encode("{\"sn\":\"AAPL\",\"si\":60,\"t\":1,\"ap\":187.2,\"vm\":7}").as_deref(),
+            Some("{\"sn\":\"AAPL\",\"si\":60,\"d\":1,\"ap\":187.2}"));
+        assert_eq!(encoder.encode("{\"sn\":\"AAPL\",\"si\":60,\"t\":1,\"ap\":187.2,\"vm\":7}").as_deref(),
+            Some("{\"sn\":\"AAPL\",\"si\":60,\"d\":1}"));
+    }
+
+    #[test]
+    fn keyframe_interval() {
+        let mut encoder = DeltaEncoder::new(3);
+        let sent:Vec<bool> = (0..7)
+            .map(|volume| encoder.encode(&format!("{{\"sn\":\"AAPL\",\"si\":60,\"vm\":{}}}", volume)).is_none())
+            .collect();
+
+        assert_eq!(sent, vec![true, false, false, true, false, false, true]);
+
+        let mut encoder = DeltaEncoder::new(0);
+
+        assert!((0..3).all(|_| encoder.encode("{\"sn\":\"AAPL\",\"si\":60}").is_none()));
+    }
+
+    #[test]
+    fn series_are_separate() {
+        let mut encoder = DeltaEncoder::new(10);
+
+        assert!(encoder.encode("{\"sn\":\"AAPL\",\"si\":60,\"vm\":1}").is_none());
+        assert!(encoder.encode("{\"sn\":\"AAPL\",\"si\":1,\"vm\":1}").is_none());
+        assert!(encoder.encode("{\"sn\":\"MSFT\",\"si\":60,\"vm\":1}").is_none());
+        assert!(encoder.encode("{\"sn\":\"AAPL\",\"si\":60,\"vm\":2}").is_some());
+        assert!(encoder.encode("{\"vm\":2}").is_none());
+    }
+
+    #[test]
+    fn reset_sends_the_next_update_in_full() {
+        let mut encoder = DeltaEncoder::new(10);
+
+        assert!(encoder.encode("{\"sn\":\"AAPL\",\"si\":60,\"vm\":1}").is_none());
+        assert!(encoder.encode("{\"sn\":\"AAPL\",\"si\":60,\"vm\":2}").is_some());
+
+        encoder.reset();
+
+        assert!(encoder.encode("{\"sn\":\"AAPL\",\"si\":60,\"vm\":3}").is_none());
+        assert!(encoder.encode("{\"sn\":\"AAPL\",\"si\":60,\"vm\":4}").is_some());
+    }
+
+    #[test]
+    fn removed_fields_send_a_keyframe() {
+        let mut encoder = DeltaEncoder::new(10);
+
+        assert!(encoder.encode("{\"sn\":\"AAPL\",\"si\":60,\"vm\":1,\"sw\":187.2}").is_none());
+        assert!(encoder.encode("{\"sn\":\"AAPL\",\"si\":60,\"vm\":2}").is_none());
+        assert_eq!(encoder.encode("{\"sn\":\"AAPL\",\"si\":60,\"vm\":2,\"sw\":187.2}").as_deref(),
+            Some("{\"sn\":\"AAPL\",\"si\":60,\"d\":1,\"sw\":187.2}"));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,567 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::value::RawValue;
+
+use crate::ingest::csv_loader::split_line;
+use crate::value_store::price::Price;
+use crate::value_store::stock_information_cache::StockInformation;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum FeedFormat {
+    Json,
+    Csv,
+}
+
+// The StockInformation fields a feed can fill, session fields are kept by the datastore
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum TargetField {
+    StockName,
+    StockInterval,
+    Timestamp,
+    AvgPrice,
+    AvgPriceOpen,
+    MinPrice,
+    MaxPrice,
+    VolumeMoved,
+    NumOfTrades,
+}
+
+impl TargetField {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "stock_name" => Some(TargetField::StockName),
+            "stock_interval" => Some(TargetField::StockInterval),
+            "timestamp" => Some(TargetField::Timestamp),
+            "avg_price" => Some(TargetField::AvgPrice),
+            "avg_price_open" => Some(TargetField::AvgPriceOpen),
+            "min_price" => Some(TargetField::MinPrice),
+            "max_price" => Some(TargetField::MaxPrice),
+            "volume_moved" => Some(TargetField::VolumeMoved),
+            "num_of_trades" => Some(TargetField::NumOfTrades),
+            _ => None,
+        }
+    }
+}
+
+// Numbers are unix time in the given unit, patterns read dates in UTC unless they hold %z
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum TimestampFormat {
+    Seconds,
+    Millis,
+    Micros,
+    Nanos,
+    Pattern(String),
+}
+
+impl TimestampFormat {
+    fn from_name(name: &str) -> Self {
+        match name {
+            "seconds" => TimestampFormat::Seconds,
+            "millis" => TimestampFormat::Millis,
+            "micros" => TimestampFormat::Micros,
+            "nanos" => TimestampFormat::Nanos,
+            "rfc3339" => TimestampFormat::Pattern("%Y-%m-%dT%H:%M:%S%f%z".to_string()),
+            pattern => TimestampFormat::Pattern(pattern.to_string()),
+        }
+    }
+
+    // Datastore timestamps are unix millis
+    fn to_millis(&self, text: &str) -> Result<i64, String> {
+        let (factor, divisor) = match self {
+            TimestampFormat::Seconds => (1000, 1),
+            TimestampFormat::Millis => (1, 1),
+            TimestampFormat::Micros => (1, 1000),
+            TimestampFormat::Nanos => (1, 1_000_000),
+            TimestampFormat::Pattern(pattern) => return parse_datetime(text, pattern),
+        };
+
+        Price::parse(text)
+            .ok()
+            .and_then(|value| value.times(Price::new(factor, 0).unwrap()))
+            .and_then(|value| value.divided_by(Price::new(divisor, 0).unwrap()))
+            .map(|value| value.round_to(0).units())
+            .ok_or_else(|| format!("Invalid timestamp {}", text))
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Conversion {
+    None,
+    Multiply(Price),
+    Divide(Price),
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum FieldSource {
+    Field(String),
+    Constant(String),
+}
+
+#[derive(Clone, Debug)]
+struct FieldMapping {
+    target: TargetField,
+    source: FieldSource,
+    conversion: Conversion,
+}
+
+// Declarative mapping of a foreign feed onto updates, one section of the feed config:
+//
+// [eodhd]
+// format=json
+// skip_without=s
+// stock_name=s
+// timestamp=t
+// timestamp_format=millis
+// avg_price=p
+// volume_moved=v * 100
+// stock_interval="0"
+//
+// Keys are StockInformation fields, values the source field. Json sources may be paths like data.price,
+// csv sources name one of the columns. A quoted value is a constant. "* factor" and "/ divisor" convert
+// units and scales exactly. Messages missing the skip_without field, e.g. status messages, are skipped
+#[derive(Clone, Debug)]
+pub struct FeedMapping {
+    name: String,
+    format: FeedFormat,
+    columns: Vec<String>,
+    skip_without: Option<String>,
+    timestamp_format: TimestampFormat,
+    fields: Vec<FieldMapping>,
+}
+
+impl FeedMapping {
+    pub fn new(name: &str) -> Self {
+        FeedMapping {
+            name: name.to_string(),
+            format: FeedFormat::Json,
+            columns: Vec::new(),
+            skip_without: None,
+            timestamp_format: TimestampFormat::Millis,
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn insert_data(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "format" => self.format = match value {
+                "json" => FeedFormat::Json,
+                "csv" => FeedFormat::Csv,
+                _ => return Err(format!("Unknown format {} of feed {}, expected json or csv", value, self.name)),
+            },
+            "columns" => self.columns = value.split(',').map(|column| column.trim().to_string()).collect(),
+            "skip_without" => self.skip_without = Some(value.to_string()),
+            "timestamp_format" => self.timestamp_format = TimestampFormat::from_name(value),
+            _ => match TargetField::from_name(key) {
+                Some(target) => {
+                    let field:FieldMapping = parse_field_mapping(target, value, &self.name)?;
+
+                    self.fields.retain(|field| field.target != target);
+                    self.fields.push(field);
+                },
+                None => return Err(format!("Unknown key {} of feed {}", key, self.name)),
+            },
+        }
+
+        Ok(())
+    }
+
+    // Every update needs a symbol and a timestamp, csv feeds their columns
+    pub fn validate(&self) -> Result<(), String> {
+        let maps = |target: TargetField| self.fields.iter().any(|field| field.target == target);
+
+        if !maps(TargetField::StockName) || !maps(TargetField::Timestamp) {
+            return Err(format!("Feed {} has to map stock_name and timestamp", self.name));
+        }
+
+        if self.format == FeedFormat::Csv && self.columns.is_empty() {
+            return Err(format!("Csv feed {} has no columns", self.name));
+        }
+
+        Ok(())
+    }
+
+    // Json messages may be arrays of records, csv messages hold one record per line
+    pub fn decode(&self, data: &str) -> Vec<Result<(StockInformation, String), String>> {
+        let records:Vec<FeedRecord> = match self.records(data) {
+            Ok(v) => v,
+            Err(e) => return vec![Err(e)],
+        };
+
+        records.iter()
+            .filter(|record| match &self.skip_without {
+                Some(field) => record.get(field).is_some(),
+                None => true,
+            })
+            .map(|record| self.decode_record(record).map(|stock_info| {
+                let json_data:String = stock_info.to_json();
+
+                (stock_info, json_data)
+            }))
+            .collect()
+    }
+
+    fn records(&self, data: &str) -> Result<Vec<FeedRecord>, String> {
+        match self.format {
+            FeedFormat::Json => {
+                let records:Result<Vec<Box<RawValue>>, serde_json::Error> = match data.trim_start().starts_with('[') {
+                    true => serde_json::from_str(data),
+                    false => serde_json::from_str(data).map(|v| vec![v]),
+                };
+
+                match records {
+                    Ok(v) => Ok(v.into_iter().map(FeedRecord::Json).collect()),
+                    Err(e) => Err(format!("Invalid {} message: {}", self.name, e)),
+                }
+            },
+            FeedFormat::Csv => Ok(data.lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| FeedRecord::Csv(self.columns.iter().cloned().zip(split_line(line)).collect()))
+                .collect()),
+        }
+    }
+
+    fn decode_record(&self, record: &FeedRecord) -> Result<StockInformation, String> {
+        let mut stock_info = StockInformation::new();
+
+        for field in self.fields.iter() {
+            let text:String = match &field.source {
+                FieldSource::Field(path) => match record.get(path) {
+                    Some(v) => v,
+                    None if field.target == TargetField::StockName || field.target == TargetField::Timestamp => {
+                        return Err(format!("Missing {}", path));
+                    },
+                    None => continue,
+                },
+                FieldSource::Constant(value) => value.clone(),
+            };
+
+            let text:&str = text.trim();
+
+            match field.target {
+                TargetField::StockName => stock_info.stock_name = text.to_string(),
+                TargetField::Timestamp => stock_info.timestamp = self.timestamp_format.to_millis(text)?,
+                TargetField::StockInterval => stock_info.stock_interval = convert_integer(text, field.conversion)? as usize,
+                TargetField::AvgPrice => stock_info.avg_price = convert(text, field.conversion)?,
+                TargetField::AvgPriceOpen => stock_info.avg_price_open = convert(text, field.conversion)?,
+                TargetField::MinPrice => stock_info.min_price = convert(text, field.conversion)?,
+                TargetField::MaxPrice => stock_info.max_price = convert(text, field.conversion)?,
+                TargetField::VolumeMoved => stock_info.volume_moved = convert_integer(text, field.conversion)?,
+                TargetField::NumOfTrades => stock_info.num_of_trades = convert_integer(text, field.conversion)?,
+            }
+        }
+
+        if stock_info.stock_name.is_empty() {
+            return Err("Missing stock name".to_string());
+        }
+
+        Ok(stock_info)
+    }
+}
+
+enum FeedRecord {
+    Json(Box<RawValue>),
+    Csv(HashMap<String, String>),
+}
+
+impl FeedRecord {
+    // Text of a field, strings without their quotes. Null counts as missing
+    fn get(&self, path: &str) -> Option<String> {
+        match self {
+            FeedRecord::Json(raw) => {
+                let mut value:Box<RawValue> = raw.clone();
+
+                for key in path.split('.') {
+                    let mut fields:BTreeMap<String, Box<RawValue>> = serde_json::from_str(value.get()).ok()?;
+                    value = fields.remove(key)?;
+                }
+
+                match value.get() {
+                    "null" => None,
+                    text if text.starts_with('"') => serde_json::from_str::<String>(text).ok(),
+                    text => Some(text.to_string()),
+                }
+            },
+            FeedRecord::Csv(values) => values.get(path).filter(|v| !v.trim().is_empty()).cloned(),
+        }
+    }
+}
+
+// "p", "p / 100", "v * 1000" or "\"60\""
+fn parse_field_mapping(target: TargetField, value: &str, feed_name: &str) -> Result<FieldMapping, String> {
+    let value:&str = value.trim();
+
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        return Ok(FieldMapping { target, source: FieldSource::Constant(value[1..value.len() - 1].to_string()), conversion: Conversion::None });
+    }
+
+    let (source, conversion) = match value.find(['*', '/']) {
+        Some(index) => {
+            let factor:Price = match Price::parse(value[index + 1..].trim()) {
+                Ok(v) if v.units() != 0 => v,
+                _ => return Err(format!("Invalid factor in {} of feed {}", value, feed_name)),
+            };
+
+            match value[index..].starts_with('*') {
+                true => (&value[..index], Conversion::Multiply(factor)),
+                false => (&value[..index], Conversion::Divide(factor)),
+            }
+        },
+        None => (value, Conversion::None),
+    };
+
+    if source.trim().is_empty() {
+        return Err(format!("Missing source field {} in feed {}", value, feed_name));
+    }
+
+    Ok(FieldMapping { target, source: FieldSource::Field(source.trim().to_string()), conversion })
+}
+
+fn convert(text: &str, conversion: Conversion) -> Result<Price, String> {
+    let value:Price = Price::parse(text)?;
+
+    let converted:Option<Price> = match conversion {
+        Conversion::None => Some(value),
+        Conversion::Multiply(factor) => value.times(factor),
+        Conversion::Divide(divisor) => value.divided_by(divisor),
+    };
+
+    converted.ok_or_else(|| format!("{} is out of range after conversion", text))
+}
+
+// Whole numbers after the conversion, rounded half away from zero
+fn convert_integer(text: &str, conversion: Conversion) -> Result<i64, String> {
+    let value:Price = convert(text, conversion)?;
+
+    match value.round_to(0).units() {
+        v if v < 0 => Err(format!("Negative value {}", text)),
+        v => Ok(v),
+    }
+}
+
+// Supports %Y, %m, %d, %H, %M, %S, %f (optional fraction of a second with its dot), %z (Z, +HH:MM or +HHMM)
+// and %%, every other character has to match as it is
+fn parse_datetime(text: &str, pattern: &str) -> Result<i64, String> {
+    let invalid = || format!("Timestamp {} doesn't match {}", text, pattern);
+
+    let mut input:&str = text.trim();
+    let mut pattern_chars = pattern.chars();
+    let (mut year, mut month, mut day, mut hour, mut minute, mut second) = (1970i64, 1i64, 1i64, 0i64, 0i64, 0i64);
+    let mut millis:i64 = 0;
+    let mut offset_minutes:i64 = 0;
+
+    while let Some(c) = pattern_chars.next() {
+        if c != '%' {
+            input = input.strip_prefix(c).ok_or_else(invalid)?;
+            continue;
+        }
+
+        match pattern_chars.next() {
+            Some('Y') => year = take_number(&mut input, 4).ok_or_else(invalid)?,
+            Some('m') => month = take_number(&mut input, 2).ok_or_else(invalid)?,
+            Some('d') => day = take_number(&mut input, 2).ok_or_else(invalid)?,
+            Some('H') => hour = take_number(&mut input, 2).ok_or_else(invalid)?,
+            Some('M') => minute = take_number(&mut input, 2).ok_or_else(invalid)?,
+            Some('S') => second = take_number(&mut input, 2).ok_or_else(invalid)?,
+            Some('f') => if let Some(rest) = input.strip_prefix('.') {
+                let digits:usize = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+                let fraction:String = format!("{:0<3}", &rest[..digits.min(3)]);
+
+                millis = fraction.parse::<i64>().map_err(|_| invalid())?;
+                input = &rest[digits..];
+            },
+            Some('z') => match input.chars().next() {
+                Some('Z') | Some('z') => input = &input[1..],
+                Some(sign @ ('+' | '-')) => {
+                    input = &input[1..];
+                    let hours:i64 = take_number(&mut input, 2).ok_or_else(invalid)?;
+                    input = input.strip_prefix(':').unwrap_or(input);
+                    let minutes:i64 = take_number(&mut input, 2).ok_or_else(invalid)?;
+
+                    offset_minutes = if sign == '-' { -(hours * 60 + minutes) } else { hours * 60 + minutes };
+                },
+                _ => return Err(invalid()),
+            },
+            Some('%') => input = input.strip_prefix('%').ok_or_else(invalid)?,
+            _ => return Err(format!("Unsupported timestamp pattern {}", pattern)),
+        }
+    }
+
+    if !input.is_empty() || !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+
+    let seconds:i64 = days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second - offset_minutes * 60;
+
+    Ok(seconds * 1000 + millis)
+}
+
+// Exactly digits digits from the front of input
+fn take_number(input: &mut &str, digits: usize) -> Option<i64> {
+    let number:&str = input.get(..digits)?;
+
+    if !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    *input = &input[digits..];
+
+    number.parse::<i64>().ok()
+}
+
+// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year:i64 = if month <= 2 { year - 1 } else { year };
+    let era:i64 = year.div_euclid(400);
+    let year_of_era:i64 = year - era * 400;
+    let day_of_year:i64 = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era:i64 = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(text: &str) -> Price {
+        Price::parse(text).unwrap()
+    }
+
+    fn mapping(name: &str, lines: &[(&str, &str)]) -> Result<FeedMapping, String> {
+        let mut feed_mapping = FeedMapping::new(name);
+
+        for (key, value) in lines.iter() {
+            feed_mapping.insert_data(key, value)?;
+        }
+
+        feed_mapping.validate()?;
+
+        Ok(feed_mapping)
+    }
+
+    #[test]
+    fn field_mapping() {
+        let field:FieldMapping = parse_field_mapping(TargetField::AvgPrice, " data.p ", "test").unwrap();
+        assert_eq!(field.source, FieldSource::Field("data.p".to_string()));
+        assert_eq!(field.conversion, Conversion::None);
+
+        let field:FieldMapping = parse_field_mapping(TargetField::AvgPrice, "p / 100", "test").unwrap();
+        assert_eq!(field.source, FieldSource::Field("p".to_string()));
+        assert_eq!(field.conversion, Conversion::Divide(price("100")));
+
+        let field:FieldMapping = parse_field_mapping(TargetField::VolumeMoved, "v*1000", "test").unwrap();
+        assert_eq!(field.conversion, Conversion::Multiply(price("1000")));
+
+        let field:FieldMapping = parse_field_mapping(TargetField::StockInterval, "\"60\"", "test").unwrap();
+        assert_eq!(field.source, FieldSource::Constant("60".to_string()));
+
+        assert!(parse_field_mapping(TargetField::AvgPrice, "p / 0", "test").is_err());
+        assert!(parse_field_mapping(TargetField::AvgPrice, "p * x", "test").is_err());
+        assert!(parse_field_mapping(TargetField::AvgPrice, " * 10", "test").is_err());
+    }
+
+    #[test]
+    fn config_errors() {
+        assert!(mapping("a", &[("format", "xml")]).is_err());
+        assert!(mapping("a", &[("price", "p")]).is_err());
+        assert!(mapping("a", &[("stock_name", "s")]).is_err());
+        assert!(mapping("a", &[("format", "csv"), ("stock_name", "s"), ("timestamp", "t")]).is_err());
+        assert!(mapping("a", &[("stock_name", "s"), ("timestamp", "t")]).is_ok());
+    }
+
+    #[test]
+    fn decodes_json() {
+        let feed_mapping = mapping("eodhd", &[
+            ("skip_without", "s"),
+            ("stock_name", "s"),
+            ("timestamp", "data.t"),
+            ("timestamp_format", "seconds"),
+            ("avg_price", "data.p / 100"),
+            ("volume_moved", "v * 100"),
+            ("stock_interval", "\"60\""),
+        ]).unwrap();
+
+        let decoded = feed_mapping.decode(r#"[{"s":"AAPL","data":{"t":1700000000,"p":18723},"v":"1.5"},{"status":"ok"}]"#);
+
+        assert_eq!(decoded.len(), 1);
+
+        let (stock_info, _) = decoded[0].as_ref().unwrap();
+        assert_eq!(stock_info.stock_name, "AAPL");
+        assert_eq!(stock_info.stock_interval, 60);
+        assert_eq!(stock_info.timestamp, 1_700_000_000_000);
+        assert_eq!(stock_info.avg_price, price("187.23"));
+        assert_eq!(stock_info.volume_moved, 150);
+
+        assert!(feed_mapping.decode(r#"{"s":"AAPL","data":{"p":1}}"#)[0].is_err());
+        assert!(feed_mapping.decode("not json")[0].is_err());
+    }
+
+    #[test]
+    fn decodes_csv() {
+        let feed_mapping = mapping("bars", &[
+            ("format", "csv"),
+            ("columns", "ticker,time,low_cents,lots"),
+            ("stock_name", "ticker"),
+            ("timestamp", "time"),
+            ("timestamp_format", "%Y-%m-%d %H:%M:%S"),
+            ("min_price", "low_cents / 100"),
+            ("volume_moved", "lots * 100"),
+        ]).unwrap();
+
+        let decoded = feed_mapping.decode("MSFT,2023-11-14 22:13:20,37005,3\n\nAAPL,2023-11-14 22:13:21,5,x\n");
+
+        assert_eq!(decoded.len(), 2);
+
+        let (stock_info, _) = decoded[0].as_ref().unwrap();
+        assert_eq!(stock_info.stock_name, "MSFT");
+        assert_eq!(stock_info.timestamp, 1_700_000_000_000);
+        assert_eq!(stock_info.min_price, price("370.05"));
+        assert_eq!(stock_info.volume_moved, 300);
+        assert!(decoded[1].is_err());
+    }
+
+    #[test]
+    fn datetime_formats() {
+        let rfc3339 = TimestampFormat::from_name("rfc3339");
+
+        assert_eq!(rfc3339.to_millis("2023-11-14T22:13:20Z"), Ok(1_700_000_000_000));
+        assert_eq!(rfc3339.to_millis("2023-11-14T22:13:20.5Z"), Ok(1_700_000_000_500));
+        assert_eq!(rfc3339.to_millis("2023-11-14T22:13:20.123456+00:00"), Ok(1_700_000_000_123));
+        assert_eq!(rfc3339.to_millis("2023-11-15T00:13:20+02:00"), Ok(1_700_000_000_000));
+        assert_eq!(rfc3339.to_millis("2023-11-14T17:13:20-0500"), Ok(1_700_000_000_000));
+        assert!(rfc3339.to_millis("2023-11-14T22:13:20").is_err());
+        assert!(rfc3339.to_millis("2023-13-14T22:13:20Z").is_err());
+
+        assert_eq!(parse_datetime("2023-11-14 22:13:20", "%Y-%m-%d %H:%M:%S"), Ok(1_700_000_000_000));
+        assert_eq!(parse_datetime("14/11/2023", "%d/%m/%Y"), Ok(1_699_920_000_000));
+        assert_eq!(parse_datetime("1969-12-31", "%Y-%m-%d"), Ok(-86_400_000));
+        assert_eq!(parse_datetime("2000-02-29 100%", "%Y-%m-%d 100%%"), Ok(951_782_400_000));
+        assert!(parse_datetime("2023-11-14 22:13", "%Y-%m-%d %H:%M:%S").is_err());
+        assert!(parse_datetime("2023-11-14x", "%Y-%m-%d").is_err());
+        assert!(parse_datetime("2023", "%Q").is_err());
+    }
+
+    #[test]
+    fn timestamp_units() {
+        assert_eq!(TimestampFormat::Seconds.to_millis("1700000000.5"), Ok(1_700_000_000_500));
+        assert_eq!(TimestampFormat::Millis.to_millis("1700000000000"), Ok(1_700_000_000_000));
+        assert_eq!(TimestampFormat::Micros.to_millis("1700000000000500"), Ok(1_700_000_000_001));
+        assert_eq!(TimestampFormat::Nanos.to_millis("1700000000000499999"), Ok(1_700_000_000_000));
+        assert!(TimestampFormat::Millis.to_millis("soon").is_err());
+    }
+
+    #[test]
+    fn unit_conversions() {
+        assert_eq!(convert("18723", Conversion::Divide(price("100"))), Ok(price("187.23")));
+        assert_eq!(convert("1.5", Conversion::Multiply(price("0.01"))), Ok(price("0.015")));
+        assert_eq!(convert("1", Conversion::Divide(price("3"))), Ok(price("0.333333333")));
+        assert!(convert("9223372036854775807", Conversion::Multiply(price("10"))).is_err());
+        assert_eq!(convert_integer("2.5", Conversion::None), Ok(3));
+        assert_eq!(convert_integer("3", Conversion::Multiply(price("100"))), Ok(300));
+        assert!(convert_integer("-1", Conversion::None).is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,114 @@
+use serde::Deserialize;
+
+use crate::persistence::history_export::ExportFilter;
+use crate::protocol::protobuf_codec;
+use crate::protocol::wire_format::{Protocol, WireFormat};
+use crate::value_store::stock_information_cache::StockInformation;
+use crate::websockets::history_query::HistoryQuery;
+
+// The one place producer updates are decoded. Unknown fields are ignored, missing numbers are 0
+pub fn decode_stock_info(json_data: &str) -> Result<StockInformation, String> {
+    if !json_data.trim_start().starts_with('{') {
+        return Err("Expected a json object".to_string());
+    }
+
+    match serde_json::from_str(json_data) {
+        Ok(v) => validate_stock_info(v),
+        Err(e) => Err(format!("Invalid update: {}", e)),
+    }
+}
+
+// Binary updates carry the same keys as json, or are a StockUpdate of the protobuf schema
+pub fn decode_binary_stock_info(protocol: Protocol, data: &[u8]) -> Result<StockInformation, String> {
+    match protocol.format {
+        WireFormat::Protobuf => validate_stock_info(protobuf_codec::decode_stock_update(data)?),
+        _ => decode_stock_info(&protocol.incoming_binary(data)?),
+    }
+}
+
+fn validate_stock_info(stock_info: StockInformation) -> Result<StockInformation, String> {
+    match stock_info.stock_name.len() {
+        0 => Err("Missing sn".to_string()),
+        _ => Ok(stock_info),
+    }
+}
+
+// Quoted and escaped, for putting names from clients and producers back into json
+pub fn json_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+pub fn error_message(error: &str) -> String {
+    format!("{{\"error\":{}}}", json_string(error))
+}
+
+// {"stock":"AAPL"}, {"history":"AAPL","si":60,"from":T1,"to":T2}, {"stats":"memory","sn":"AAPL"}, {"delta":true}
+// {"load_csv":"bars.csv"} or {"export_history":"bars.parquet","symbols":["AAPL"],"si":60,"from":T1,"to":T2}
+pub enum ClientCommand {
+    Subscribe(String),
+    History(HistoryQuery),
+    Stats { stats: String, stock_name: Option<String> },
+    Delta(bool),
+    LoadCsv(String),
+    ExportHistory { file: String, filter: ExportFilter },
+}
+
+#[derive(Deserialize)]
+struct CommandFields {
+    stock: Option<String>,
+    history: Option<String>,
+    si: Option<usize>,
+    from: Option<i64>,
+    to: Option<i64>,
+    stats: Option<String>,
+    sn: Option<String>,
+    delta: Option<bool>,
+    load_csv: Option<String>,
+    export_history: Option<String>,
+    symbols: Option<Vec<String>>,
+}
+
+impl ClientCommand {
+    pub fn decode(json_data: &str) -> Result<Self, String> {
+        if !json_data.trim_start().starts_with('{') {
+            return Err("Expected a json object".to_string());
+        }
+
+        match serde_json::from_str(json_data) {
+            Ok(v) => Self::from_fields(v),
+            Err(e) => Err(format!("Invalid command: {}", e)),
+        }
+    }
+
+    pub fn decode_binary(protocol: Protocol, data: &[u8]) -> Result<Self, String> {
+        match protocol.format {
+            WireFormat::Protobuf => protobuf_codec::decode_client_command(data),
+            _ => Self::decode(&protocol.incoming_binary(data)?),
+        }
+    }
+
+    // Commands that touch files of the datastore, only accepted on the admin port
+    pub fn is_admin(&self) -> bool {
+        matches!(self, ClientCommand::LoadCsv(_) | ClientCommand::ExportHistory { .. })
+    }
+
+    fn from_fields(fields: CommandFields) -> Result<Self, String> {
+        match fields {
+            CommandFields { history: Some(stock_name), si, from, to, .. } => Ok(ClientCommand::History(HistoryQuery::new(
+                stock_name,
+                si.ok_or("Missing si")?,
+                from.ok_or("Missing from")?,
+                to.ok_or("Missing to")?
+            ))),
+            CommandFields { stats: Some(stats), sn, .. } => Ok(ClientCommand::Stats { stats, stock_name: sn }),
+            CommandFields { stock: Some(stock_name), .. } => Ok(ClientCommand::Subscribe(stock_name)),
+            CommandFields { delta: Some(enabled), .. } => Ok(ClientCommand::Delta(enabled)),
+            CommandFields { load_csv: Some(file), .. } => Ok(ClientCommand::LoadCsv(file)),
+            CommandFields { export_history: Some(file), symbols, si, from, to, .. } => Ok(ClientCommand::ExportHistory {
+                file,
+                filter: ExportFilter::new(symbols.unwrap_or_default(), si, from, to),
+            }),
+            _ => Err("Unknown command, expected stock, history, stats, delta, load_csv or export_history".to_string()),
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,8 @@
+pub mod delta_encoder;
+pub mod feed_mapping;
+pub mod json_codec;
+pub mod polygon_codec;
+pub mod price_fields;
+pub mod protobuf_codec;
+pub mod protocol_version;
+pub mod wire_format;
\ No newline at end of file
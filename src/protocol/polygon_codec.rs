@@ -0,0 +1,86 @@
+use serde::Deserialize;
+use serde_json::value::RawValue;
+
+use crate::value_store::price::Price;
+use crate::value_store::stock_information_cache::StockInformation;
+
+// Aggregate as Polygon streams it, {"ev":"AM","sym":"AAPL","v":4110,"vw":187.2301,"o":187.1,"c":187.3,
+// "h":187.4,"l":187.05,"z":22,"s":1700000000000,"e":1700000060000}. Fields the datastore has no place for are ignored
+#[derive(Deserialize)]
+struct PolygonAggregate {
+    sym: String,
+    v: f64,
+    vw: Price,
+    o: Price,
+    h: Price,
+    l: Price,
+    #[serde(default)]
+    z: f64,
+    s: i64,
+}
+
+#[derive(Deserialize)]
+struct PolygonEvent {
+    ev: String,
+}
+
+// Second ("A") and minute ("AM") aggregates, alone or in an array, mapped onto updates with the short keys
+// the datastore keeps. Status messages are skipped, every other event is an error of its own
+pub fn decode_aggregates(json_data: &str) -> Vec<Result<(StockInformation, String), String>> {
+    let messages:Result<Vec<Box<RawValue>>, serde_json::Error> = match json_data.trim_start().starts_with('[') {
+        true => serde_json::from_str(json_data),
+        false => serde_json::from_str(json_data).map(|v| vec![v]),
+    };
+
+    let messages:Vec<Box<RawValue>> = match messages {
+        Ok(v) => v,
+        Err(e) => return vec![Err(format!("Invalid polygon message: {}", e))],
+    };
+
+    messages.iter()
+        .filter_map(|message| {
+            let interval:usize = match serde_json::from_str::<PolygonEvent>(message.get()) {
+                Ok(v) if v.ev == "A" => 1,
+                Ok(v) if v.ev == "AM" => 60,
+                Ok(v) if v.ev == "status" => return None,
+                Ok(v) => return Some(Err(format!("Unsupported polygon event {}", v.ev))),
+                Err(e) => return Some(Err(format!("Invalid polygon message: {}", e))),
+            };
+
+            Some(decode_aggregate(message.get(), interval).map(|stock_info| {
+                let json_data:String = stock_info.to_json();
+
+                (stock_info, json_data)
+            }))
+        })
+        .collect()
+}
+
+// The bar starts at s, its average price is the volume weighted one. Polygon sends the average trade size
+// instead of the number of trades, so that is derived from the volume
+fn decode_aggregate(json_data: &str, interval: usize) -> Result<StockInformation, String> {
+    let aggregate:PolygonAggregate = match serde_json::from_str(json_data) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Invalid polygon aggregate: {}", e)),
+    };
+
+    if aggregate.sym.is_empty() {
+        return Err("Missing sym".to_string());
+    }
+
+    Ok(StockInformation {
+        stock_name: aggregate.sym,
+        stock_interval: interval,
+        timestamp: aggregate.s,
+        avg_price: aggregate.vw,
+        avg_price_open: aggregate.o,
+        min_price: aggregate.l,
+        max_price: aggregate.h,
+        volume_moved: aggregate.v.round() as i64,
+        num_of_trades: match aggregate.z > 0.0 {
+            true => (aggregate.v / aggregate.z).round() as i64,
+            false => 0,
+        },
+        ..StockInformation::new()
+    })
+}
\ No newline at end of file
@@ -0,0 +1,49 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::value_store::price::Price;
+
+pub static NO_PRICES: PriceFields = PriceFields { ap: None, op: None, mn: None, mx: None, sw: None, bars: Vec::new() };
+
+// Prices of an update, or of every bar of a history chunk, read from the json text so they stay exact
+// when it's transcoded into another encoding
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct PriceFields {
+    pub ap: Option<Price>,
+    pub op: Option<Price>,
+    pub mn: Option<Price>,
+    pub mx: Option<Price>,
+    pub sw: Option<Price>,
+    pub bars: Vec<PriceFields>,
+}
+
+impl PriceFields {
+    pub fn from_json(json_data: &str) -> Self {
+        serde_json::from_str(json_data).unwrap_or_default()
+    }
+
+    pub fn bar(&self, index: usize) -> &PriceFields {
+        self.bars.get(index).unwrap_or(&NO_PRICES)
+    }
+
+    // Msgpack and cbor have no decimal type, prices are put in as decimal strings there
+    pub fn write_strings(&self, value: &mut Value) {
+        let object = match value.as_object_mut() {
+            Some(v) => v,
+            None => return,
+        };
+
+        for (key, price) in [("ap", self.ap), ("op", self.op), ("mn", self.mn), ("mx", self.mx), ("sw", self.sw)] {
+            if let (Some(price), Some(field)) = (price, object.get_mut(key)) {
+                *field = Value::String(price.to_string());
+            }
+        }
+
+        if let Some(Value::Array(bars)) = object.get_mut("bars") {
+            for (index, bar) in bars.iter_mut().enumerate() {
+                self.bar(index).write_strings(bar);
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,308 @@
+// Messages of proto/stock_datastore.proto. They are written by hand with prost's derive,
+// so the schema has to be changed together with them
+use prost::{Message, Oneof};
+use serde_json::Value;
+
+use crate::protocol::json_codec::ClientCommand;
+use crate::protocol::price_fields::PriceFields;
+use crate::value_store::price::Price;
+use crate::value_store::stock_information_cache::StockInformation;
+use crate::websockets::history_query::HistoryQuery;
+
+// units * 10^-scale, prices are exact decimals
+#[derive(Clone, Copy, PartialEq, Message)]
+pub struct Decimal {
+    #[prost(int64, tag = "1")]
+    pub units: i64,
+    #[prost(uint32, tag = "2")]
+    pub scale: u32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct StockUpdate {
+    #[prost(string, tag = "1")]
+    pub sn: String,
+    #[prost(uint32, tag = "2")]
+    pub si: u32,
+    #[prost(int64, tag = "3")]
+    pub t: i64,
+    #[prost(message, optional, tag = "4")]
+    pub ap: Option<Decimal>,
+    #[prost(message, optional, tag = "5")]
+    pub op: Option<Decimal>,
+    #[prost(message, optional, tag = "6")]
+    pub mn: Option<Decimal>,
+    #[prost(message, optional, tag = "7")]
+    pub mx: Option<Decimal>,
+    #[prost(int64, tag = "8")]
+    pub vm: i64,
+    #[prost(int64, tag = "9")]
+    pub nt: i64,
+    #[prost(message, optional, tag = "10")]
+    pub sw: Option<Decimal>,
+    #[prost(int64, optional, tag = "11")]
+    pub sv: Option<i64>,
+    #[prost(int64, optional, tag = "12")]
+    pub st: Option<i64>,
+    #[prost(uint64, optional, tag = "13")]
+    pub rt: Option<u64>,
+    #[prost(uint64, optional, tag = "14")]
+    pub wt: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Subscribe {
+    #[prost(string, tag = "1")]
+    pub stock: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct HistoryRequest {
+    #[prost(string, tag = "1")]
+    pub history: String,
+    #[prost(uint32, tag = "2")]
+    pub si: u32,
+    #[prost(int64, tag = "3")]
+    pub from: i64,
+    #[prost(int64, tag = "4")]
+    pub to: i64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct StatsRequest {
+    #[prost(string, tag = "1")]
+    pub stats: String,
+    #[prost(string, optional, tag = "2")]
+    pub sn: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct DeltaRequest {
+    #[prost(bool, tag = "1")]
+    pub enabled: bool,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+pub enum Command {
+    #[prost(message, tag = "1")]
+    Subscribe(Subscribe),
+    #[prost(message, tag = "2")]
+    History(HistoryRequest),
+    #[prost(message, tag = "3")]
+    Stats(StatsRequest),
+    #[prost(message, tag = "4")]
+    Delta(DeltaRequest),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ClientCommandMessage {
+    #[prost(oneof = "Command", tags = "1, 2, 3, 4")]
+    pub command: Option<Command>,
+}
+
+// Only the fields that changed since the last update of the series are set
+#[derive(Clone, PartialEq, Message)]
+pub struct StockDelta {
+    #[prost(string, tag = "1")]
+    pub sn: String,
+    #[prost(uint32, tag = "2")]
+    pub si: u32,
+    #[prost(int64, optional, tag = "3")]
+    pub t: Option<i64>,
+    #[prost(message, optional, tag = "4")]
+    pub ap: Option<Decimal>,
+    #[prost(message, optional, tag = "5")]
+    pub op: Option<Decimal>,
+    #[prost(message, optional, tag = "6")]
+    pub mn: Option<Decimal>,
+    #[prost(message, optional, tag = "7")]
+    pub mx: Option<Decimal>,
+    #[prost(int64, optional, tag = "8")]
+    pub vm: Option<i64>,
+    #[prost(int64, optional, tag = "9")]
+    pub nt: Option<i64>,
+    #[prost(message, optional, tag = "10")]
+    pub sw: Option<Decimal>,
+    #[prost(int64, optional, tag = "11")]
+    pub sv: Option<i64>,
+    #[prost(int64, optional, tag = "12")]
+    pub st: Option<i64>,
+    #[prost(uint64, optional, tag = "13")]
+    pub rt: Option<u64>,
+    #[prost(uint64, optional, tag = "14")]
+    pub wt: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct HistoryChunk {
+    #[prost(string, tag = "1")]
+    pub history: String,
+    #[prost(uint32, tag = "2")]
+    pub si: u32,
+    #[prost(uint32, tag = "3")]
+    pub chunk: u32,
+    #[prost(bool, tag = "4")]
+    pub last: bool,
+    #[prost(message, repeated, tag = "5")]
+    pub bars: Vec<StockUpdate>,
+    #[prost(bool, tag = "6")]
+    pub truncated: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Stats {
+    #[prost(string, tag = "1")]
+    pub stats: String,
+    #[prost(string, tag = "2")]
+    pub json: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Error {
+    #[prost(string, tag = "1")]
+    pub error: String,
+}
+
+#[derive(Clone, PartialEq, Oneof)]
+pub enum Reply {
+    #[prost(message, tag = "1")]
+    Update(StockUpdate),
+    #[prost(message, tag = "2")]
+    History(HistoryChunk),
+    #[prost(message, tag = "3")]
+    Stats(Stats),
+    #[prost(message, tag = "4")]
+    Error(Error),
+    #[prost(message, tag = "5")]
+    Delta(StockDelta),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct ServerMessage {
+    #[prost(oneof = "Reply", tags = "1, 2, 3, 4, 5")]
+    pub message: Option<Reply>,
+}
+
+pub fn decode_stock_update(data: &[u8]) -> Result<StockInformation, String> {
+    let update:StockUpdate = match StockUpdate::decode(data) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Invalid protobuf: {}", e)),
+    };
+
+    Ok(StockInformation {
+        stock_name: update.sn,
+        stock_interval: update.si as usize,
+        timestamp: update.t,
+        avg_price: to_price(update.ap)?,
+        avg_price_open: to_price(update.op)?,
+        min_price: to_price(update.mn)?,
+        max_price: to_price(update.mx)?,
+        volume_moved: update.vm,
+        num_of_trades: update.nt,
+        session_vwap: to_price(update.sw)?,
+        session_volume: update.sv.unwrap_or(0),
+        session_trades: update.st.unwrap_or(0),
+    })
+}
+
+fn to_price(decimal: Option<Decimal>) -> Result<Price, String> {
+    match decimal {
+        Some(v) => Price::new(v.units, v.scale),
+        None => Ok(Price::default()),
+    }
+}
+
+fn to_decimal(price: Option<Price>) -> Option<Decimal> {
+    price.map(|v| Decimal { units: v.units(), scale: v.scale() })
+}
+
+pub fn decode_client_command(data: &[u8]) -> Result<ClientCommand, String> {
+    let message:ClientCommandMessage = match ClientCommandMessage::decode(data) {
+        Ok(v) => v,
+        Err(e) => return Err(format!("Invalid protobuf: {}", e)),
+    };
+
+    match message.command {
+        Some(Command::Subscribe(v)) => Ok(ClientCommand::Subscribe(v.stock)),
+        Some(Command::History(v)) => Ok(ClientCommand::History(HistoryQuery::new(v.history, v.si as usize, v.from, v.to))),
+        Some(Command::Stats(v)) => Ok(ClientCommand::Stats { stats: v.stats, stock_name: v.sn }),
+        Some(Command::Delta(v)) => Ok(ClientCommand::Delta(v.enabled)),
+        None => Err("Unknown command, expected stock, history, stats or delta".to_string()),
+    }
+}
+
+// Everything the datastore sends is json internally, it's sorted into a reply by its keys.
+// Fields that aren't in the schema are dropped. Prices are read from the json text, so they stay exact
+pub fn encode_server_message(json_data: &str) -> Option<Vec<u8>> {
+    let value:Value = serde_json::from_str(json_data).ok()?;
+    let prices:PriceFields = PriceFields::from_json(json_data);
+
+    let reply:Reply = if let Some(error) = value.get("error") {
+        Reply::Error(Error { error: error.as_str().unwrap_or_default().to_string() })
+    } else if value.get("history").is_some() {
+        Reply::History(HistoryChunk {
+            history: str_field(&value, "history"),
+            si: u64_field(&value, "si") as u32,
+            chunk: u64_field(&value, "chunk") as u32,
+            last: value.get("last").and_then(Value::as_bool).unwrap_or(false),
+            bars: value.get("bars").and_then(Value::as_array)
+                .map(|bars| bars.iter().enumerate().map(|(index, bar)| to_stock_update(bar, prices.bar(index))).collect())
+                .unwrap_or_default(),
+            truncated: value.get("truncated").and_then(Value::as_bool).unwrap_or(false),
+        })
+    } else if value.get("d").is_some() {
+        Reply::Delta(to_stock_delta(&value, &prices))
+    } else if value.get("stats").is_some() {
+        Reply::Stats(Stats { stats: str_field(&value, "stats"), json: json_data.to_string() })
+    } else {
+        Reply::Update(to_stock_update(&value, &prices))
+    };
+
+    Some(ServerMessage { message: Some(reply) }.encode_to_vec())
+}
+
+fn to_stock_update(value: &Value, prices: &PriceFields) -> StockUpdate {
+    StockUpdate {
+        sn: str_field(value, "sn"),
+        si: u64_field(value, "si") as u32,
+        t: value.get("t").and_then(Value::as_i64).unwrap_or(0),
+        ap: to_decimal(prices.ap),
+        op: to_decimal(prices.op),
+        mn: to_decimal(prices.mn),
+        mx: to_decimal(prices.mx),
+        vm: value.get("vm").and_then(Value::as_i64).unwrap_or(0),
+        nt: value.get("nt").and_then(Value::as_i64).unwrap_or(0),
+        sw: to_decimal(prices.sw),
+        sv: value.get("sv").and_then(Value::as_i64),
+        st: value.get("st").and_then(Value::as_i64),
+        rt: value.get("rt").and_then(Value::as_u64),
+        wt: value.get("wt").and_then(Value::as_u64),
+    }
+}
+
+fn to_stock_delta(value: &Value, prices: &PriceFields) -> StockDelta {
+    StockDelta {
+        sn: str_field(value, "sn"),
+        si: u64_field(value, "si") as u32,
+        t: value.get("t").and_then(Value::as_i64),
+        ap: to_decimal(prices.ap),
+        op: to_decimal(prices.op),
+        mn: to_decimal(prices.mn),
+        mx: to_decimal(prices.mx),
+        vm: value.get("vm").and_then(Value::as_i64),
+        nt: value.get("nt").and_then(Value::as_i64),
+        sw: to_decimal(prices.sw),
+        sv: value.get("sv").and_then(Value::as_i64),
+        st: value.get("st").and_then(Value::as_i64),
+        rt: value.get("rt").and_then(Value::as_u64),
+        wt: value.get("wt").and_then(Value::as_u64),
+    }
+}
+
+fn str_field(value: &Value, key: &str) -> String {
+    value.get(key).and_then(Value::as_str).unwrap_or_default().to_string()
+}
+
+fn u64_field(value: &Value, key: &str) -> u64 {
+    value.get(key).and_then(Value::as_u64).unwrap_or(0)
+}
\ No newline at end of file
@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Value};
+use serde_json::value::{RawValue, to_raw_value};
+
+type RawFields = BTreeMap<String, Box<RawValue>>;
+
+// Version 1 keys and what version 2 calls them. Keys that aren't listed are the same in both,
+// e.g. the commands stock, history, from, to, stats and delta
+const V2_KEYS: &[(&str, &str)] = &[
+    ("sn", "stock_name"),
+    ("si", "interval"),
+    ("t", "timestamp"),
+    ("ap", "avg_price"),
+    ("op", "open_price"),
+    ("mn", "min_price"),
+    ("mx", "max_price"),
+    ("vm", "volume"),
+    ("nt", "trades"),
+    ("sw", "session_vwap"),
+    ("sv", "session_volume"),
+    ("st", "session_trades"),
+    ("rt", "received_at"),
+    ("wt", "written_at"),
+    ("d", "is_delta"),
+];
+
+// Version of the message keys a connection speaks, picked in the handshake together with the encoding.
+// Version 1 are the short keys the datastore keeps internally, so only other versions are translated.
+// Version 2 spells the keys out and every message carries "v":2
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProtocolVersion {
+    V1,
+    V2,
+}
+
+impl ProtocolVersion {
+    pub fn from_number(number: u64) -> Option<Self> {
+        match number {
+            1 => Some(ProtocolVersion::V1),
+            2 => Some(ProtocolVersion::V2),
+            _ => None,
+        }
+    }
+
+    pub fn number(&self) -> u64 {
+        match self {
+            ProtocolVersion::V1 => 1,
+            ProtocolVersion::V2 => 2,
+        }
+    }
+
+    // Producer updates and client commands as the datastore reads them. A "v" in the message has to match
+    pub fn incoming(&self, json_data: &str) -> Result<String, String> {
+        if *self == ProtocolVersion::V1 {
+            return Ok(json_data.to_string());
+        }
+
+        let mut fields:RawFields = match serde_json::from_str(json_data) {
+            Ok(v) => v,
+            Err(e) => return Err(format!("Invalid message: {}", e)),
+        };
+
+        if let Some(version) = fields.remove("v") {
+            if serde_json::from_str::<u64>(version.get()).ok() != Some(self.number()) {
+                return Err(format!("Expected protocol version {}, got {}", self.number(), version.get()));
+            }
+        }
+
+        Ok(serde_json::to_string(&self.rename_raw(fields, |key| self.internal_key(key))).unwrap())
+    }
+
+    // Messages of the datastore as this version reads them. Anything that isn't a json object,
+    // e.g. the stock list sent to producers, is left as it is
+    pub fn outgoing(&self, json_data: &str) -> String {
+        if *self == ProtocolVersion::V1 {
+            return json_data.to_string();
+        }
+
+        let fields:RawFields = match serde_json::from_str(json_data) {
+            Ok(v) => v,
+            Err(_) => return json_data.to_string(),
+        };
+
+        let mut fields:RawFields = self.rename_raw(fields, |key| self.external_key(key));
+        fields.insert("v".to_string(), to_raw_value(&self.number()).unwrap());
+
+        serde_json::to_string(&fields).unwrap()
+    }
+
+    // Same as outgoing for messages that are transcoded into msgpack or cbor
+    pub fn outgoing_value(&self, value: &mut Value) {
+        if *self == ProtocolVersion::V1 {
+            return;
+        }
+
+        if let Value::Object(fields) = value {
+            *fields = self.rename_value(std::mem::take(fields));
+            fields.insert("v".to_string(), Value::from(self.number()));
+        }
+    }
+
+    fn internal_key(&self, key: &str) -> String {
+        match V2_KEYS.iter().find(|(_, external)| *external == key) {
+            Some((internal, _)) => internal.to_string(),
+            None => key.to_string(),
+        }
+    }
+
+    fn external_key(&self, key: &str) -> String {
+        match V2_KEYS.iter().find(|(internal, _)| *internal == key) {
+            Some((_, external)) => external.to_string(),
+            None => key.to_string(),
+        }
+    }
+
+    // The bars of a history chunk are renamed too. Values are kept as their text, so prices stay exact
+    fn rename_raw<F: Fn(&str) -> String + Copy>(&self, fields: RawFields, rename: F) -> RawFields {
+        fields.into_iter()
+            .map(|(key, value)| {
+                let bars:Option<Vec<RawFields>> = match key.as_str() {
+                    "bars" => serde_json::from_str(value.get()).ok(),
+                    _ => None,
+                };
+
+                let value:Box<RawValue> = match bars {
+                    Some(bars) => {
+                        let bars:Vec<RawFields> = bars.into_iter().map(|bar| self.rename_raw(bar, rename)).collect();
+
+                        to_raw_value(&bars).unwrap()
+                    },
+                    None => value,
+                };
+
+                (rename(&key), value)
+            })
+            .collect()
+    }
+
+    fn rename_value(&self, fields: Map<String, Value>) -> Map<String, Value> {
+        fields.into_iter()
+            .map(|(key, value)| {
+                let value:Value = match (key.as_str(), value) {
+                    ("bars", Value::Array(bars)) => Value::Array(bars.into_iter()
+                        .map(|bar| match bar {
+                            Value::Object(v) => Value::Object(self.rename_value(v)),
+                            other => other,
+                        })
+                        .collect()),
+                    (_, value) => value,
+                };
+
+                (self.external_key(&key), value)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1_UPDATE: &str = "{\"ap\":187.20,\"lg_sent\":42,\"mn\":187.1,\"mx\":187.3,\"nt\":3,\"op\":187.15,\"si\":60,\"sn\":\"AAPL\",\"t\":60000,\"vm\":100}";
+    const V2_UPDATE: &str = "{\"avg_price\":187.20,\"interval\":60,\"lg_sent\":42,\"max_price\":187.3,\"min_price\":187.1,\"open_price\":187.15,\"stock_name\":\"AAPL\",\"timestamp\":60000,\"trades\":3,\"v\":2,\"volume\":100}";
+
+    #[test]
+    fn version_one_is_left_as_it_is() {
+        assert_eq!(ProtocolVersion::V1.incoming(V1_UPDATE).unwrap(), V1_UPDATE);
+        assert_eq!(ProtocolVersion::V1.outgoing(V1_UPDATE), V1_UPDATE);
+    }
+
+    #[test]
+    fn outgoing_and_back() {
+        let outgoing:String = ProtocolVersion::V2.outgoing(V1_UPDATE);
+
+        assert_eq!(outgoing, V2_UPDATE);
+        assert_eq!(ProtocolVersion::V2.incoming(&outgoing).unwrap(), V1_UPDATE);
+    }
+
+    #[test]
+    fn incoming_and_back() {
+        let incoming:String = ProtocolVersion::V2.incoming(V2_UPDATE).unwrap();
+
+        assert_eq!(incoming, V1_UPDATE);
+        assert_eq!(ProtocolVersion::V2.outgoing(&incoming), V2_UPDATE);
+    }
+
+    #[test]
+    fn unknown_fields_pass_through() {
+        let incoming:String = ProtocolVersion::V2.incoming("{\"stock_name\":\"AAPL\",\"custom\":{\"vm\":1},\"lg_sent\":42}").unwrap();
+
+        assert_eq!(incoming, "{\"custom\":{\"vm\":1},\"lg_sent\":42,\"sn\":\"AAPL\"}");
+        assert_eq!(ProtocolVersion::V2.outgoing(&incoming), "{\"custom\":{\"vm\":1},\"lg_sent\":42,\"stock_name\":\"AAPL\",\"v\":2}");
+    }
+
+    #[test]
+    fn history_bars_are_renamed() {
+        let chunk:&str = "{\"bars\":[{\"ap\":187.20,\"sn\":\"AAPL\"}],\"history\":\"AAPL\",\"si\":60}";
+        let outgoing:String = ProtocolVersion::V2.outgoing(chunk);
+
+        assert_eq!(outgoing, "{\"bars\":[{\"avg_price\":187.20,\"stock_name\":\"AAPL\"}],\"history\":\"AAPL\",\"interval\":60,\"v\":2}");
+
+        let mut value:Value = serde_json::from_str(chunk).unwrap();
+        ProtocolVersion::V2.outgoing_value(&mut value);
+
+        assert_eq!(value, serde_json::from_str::<Value>(&outgoing).unwrap());
+    }
+
+    #[test]
+    fn incoming_version_has_to_match() {
+        assert!(ProtocolVersion::V2.incoming("{\"stock\":\"AAPL\",\"v\":2}").is_ok());
+        assert!(ProtocolVersion::V2.incoming("{\"stock\":\"AAPL\",\"v\":1}").is_err());
+        assert!(ProtocolVersion::V2.incoming("not json").is_err());
+        assert_eq!(ProtocolVersion::V2.outgoing("[\"AAPL\"]"), "[\"AAPL\"]");
+    }
+}
\ No newline at end of file
@@ -0,0 +1,195 @@
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::handshake::server::{Request, Response},
+    tungstenite::http::HeaderValue,
+    tungstenite::Message,
+    WebSocketStream,
+};
+
+use crate::protocol::feed_mapping::FeedMapping;
+use crate::protocol::price_fields::PriceFields;
+use crate::protocol::protobuf_codec;
+use crate::protocol::protocol_version::ProtocolVersion;
+
+// Encoding of a connection, picked from the websocket subprotocols the client offers.
+// Text frames are always json, binary frames use the negotiated encoding
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WireFormat {
+    Json,
+    MessagePack,
+    Cbor,
+    Protobuf,
+}
+
+impl WireFormat {
+    pub fn from_subprotocol(subprotocol: &str) -> Option<Self> {
+        match subprotocol.trim() {
+            "json" => Some(WireFormat::Json),
+            "msgpack" => Some(WireFormat::MessagePack),
+            "cbor" => Some(WireFormat::Cbor),
+            "protobuf" => Some(WireFormat::Protobuf),
+            _ => None,
+        }
+    }
+
+    pub fn subprotocol(&self) -> &'static str {
+        match self {
+            WireFormat::Json => "json",
+            WireFormat::MessagePack => "msgpack",
+            WireFormat::Cbor => "cbor",
+            WireFormat::Protobuf => "protobuf",
+        }
+    }
+
+    // Json is sent as it is, msgpack and cbor are transcoded from it so every field survives, with prices
+    // as decimal strings. Protobuf only keeps the fields of the schema, its field numbers don't change
+    // between protocol versions. Anything that isn't json stays a text frame
+    pub fn encode(&self, json_data: &str, version: ProtocolVersion) -> Message {
+        let mut value:serde_json::Value = match self {
+            WireFormat::Json => return Message::Text(version.outgoing(json_data).into()),
+            WireFormat::Protobuf => return match protobuf_codec::encode_server_message(json_data) {
+                Some(v) => Message::Binary(v.into()),
+                None => Message::Text(json_data.into()),
+            },
+            _ => match serde_json::from_str(json_data) {
+                Ok(v) => v,
+                Err(_) => return Message::Text(json_data.into()),
+            },
+        };
+
+        PriceFields::from_json(json_data).write_strings(&mut value);
+        version.outgoing_value(&mut value);
+
+        let encoded:Result<Vec<u8>, String> = match self {
+            WireFormat::MessagePack => rmp_serde::to_vec(&value).map_err(|e| e.to_string()),
+            WireFormat::Cbor => {
+                let mut data:Vec<u8> = Vec::new();
+
+                ciborium::into_writer(&value, &mut data).map(|_| data).map_err(|e| e.to_string())
+            },
+            WireFormat::Json | WireFormat::Protobuf => unreachable!(),
+        };
+
+        match encoded {
+            Ok(v) => Message::Binary(v.into()),
+            Err(_) => Message::Text(json_data.into()),
+        }
+    }
+
+    // Transcoded into json, so binary messages follow the same rules as json ones,
+    // e.g. an integer is fine where a float is expected. Protobuf messages have their own decoders
+    pub fn transcode_to_json(&self, data: &[u8]) -> Result<String, String> {
+        let value:serde_json::Value = match self {
+            WireFormat::Json => return Err("Binary frames need the msgpack, cbor or protobuf subprotocol".to_string()),
+            WireFormat::Protobuf => return Err("Protobuf messages are decoded by the protobuf codec".to_string()),
+            WireFormat::MessagePack => rmp_serde::from_slice(data).map_err(|e| format!("Invalid msgpack: {}", e))?,
+            WireFormat::Cbor => ciborium::from_reader(data).map_err(|e| format!("Invalid cbor: {}", e))?,
+        };
+
+        if !value.is_object() {
+            return Err("Expected a map".to_string());
+        }
+
+        Ok(value.to_string())
+    }
+}
+
+// What a producer sends. Besides datastore updates the ingest port takes the aggregates of a market data
+// vendor and maps them onto updates, so they don't need translating before they are sent. Mapped feeds
+// are the index of their mapping in the feed config
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Feed {
+    Datastore,
+    Polygon,
+    Mapped(usize),
+}
+
+// Encoding, protocol version and feed of a connection
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Protocol {
+    pub format: WireFormat,
+    pub version: ProtocolVersion,
+    pub feed: Feed,
+}
+
+impl Protocol {
+    // "msgpack" speaks version 1, "msgpack.v2" version 2. "polygon" are Polygon aggregates as json,
+    // "feed.eodhd" the messages of the feed mapping named eodhd
+    pub fn from_subprotocol(subprotocol: &str, feed_mappings: &[FeedMapping]) -> Option<Self> {
+        if subprotocol.trim() == "polygon" {
+            return Some(Protocol { format: WireFormat::Json, version: ProtocolVersion::V1, feed: Feed::Polygon });
+        }
+
+        if let Some(name) = subprotocol.trim().strip_prefix("feed.") {
+            let index:usize = feed_mappings.iter().position(|feed_mapping| feed_mapping.name() == name)?;
+
+            return Some(Protocol { format: WireFormat::Json, version: ProtocolVersion::V1, feed: Feed::Mapped(index) });
+        }
+
+        let (format, version) = match subprotocol.trim().rsplit_once(".v") {
+            Some((format, version)) => (format, ProtocolVersion::from_number(version.parse::<u64>().ok()?)?),
+            None => (subprotocol, ProtocolVersion::V1),
+        };
+
+        Some(Protocol { format: WireFormat::from_subprotocol(format)?, version, feed: Feed::Datastore })
+    }
+
+    pub fn subprotocol(&self) -> String {
+        match self.feed {
+            Feed::Polygon => return "polygon".to_string(),
+            Feed::Mapped(_) => return "feed".to_string(),
+            Feed::Datastore => (),
+        }
+
+        match self.version {
+            ProtocolVersion::V1 => self.format.subprotocol().to_string(),
+            version => format!("{}.v{}", self.format.subprotocol(), version.number()),
+        }
+    }
+
+    pub fn encode(&self, json_data: &str) -> Message {
+        self.format.encode(json_data, self.version)
+    }
+
+    // Text frames as the datastore reads them
+    pub fn incoming(&self, json_data: &str) -> Result<String, String> {
+        self.version.incoming(json_data)
+    }
+
+    // Msgpack and cbor frames as the datastore reads them
+    pub fn incoming_binary(&self, data: &[u8]) -> Result<String, String> {
+        self.version.incoming(&self.format.transcode_to_json(data)?)
+    }
+}
+
+// Accepts the websocket handshake and picks the first subprotocol offered that we support, feeds only
+// on connections that get feed mappings. The chosen subprotocol is answered as it was offered.
+// Clients that offer none get json version 1. The error type of the callback is given by tungstenite
+#[allow(clippy::result_large_err)]
+pub async fn accept_with_protocol(stream: TcpStream,
+                                  feed_mappings: Option<&[FeedMapping]>) -> Option<(WebSocketStream<TcpStream>, Protocol)> {
+    let mut protocol:Protocol = Protocol { format: WireFormat::Json, version: ProtocolVersion::V1, feed: Feed::Datastore };
+
+    let websocket = accept_hdr_async(stream, |request: &Request, mut response: Response| {
+        let offered:Option<(&str, Protocol)> = request.headers()
+            .get_all("Sec-WebSocket-Protocol")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|value| Protocol::from_subprotocol(value, feed_mappings.unwrap_or(&[])).map(|v| (value.trim(), v)))
+            .find(|(_, v)| feed_mappings.is_some() || v.feed == Feed::Datastore);
+
+        if let Some((subprotocol, v)) = offered {
+            protocol = v;
+            response.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_str(subprotocol).unwrap());
+        }
+
+        Ok(response)
+    }).await;
+
+    match websocket {
+        Ok(v) => Some((v, protocol)),
+        Err(_) => None,
+    }
+}
\ No newline at end of file
@@ -0,0 +1,369 @@
+use std::mem;
+
+use im::{HashMap, Vector};
+
+use crate::value_store::stock_information_cache::{
+    StockInformation, SessionStats,
+};
+use crate::protocol::json_codec::decode_stock_info;
+use crate::value_store::symbol_memory::{SymbolMemory, ENTRY_OVERHEAD};
+
+const SESSION_LENGTH: i64 = 86_400_000;
+const MAX_HISTORY_LENGTH: usize = 120;
+
+// Holds every series of the symbols hashed onto this shard. All maps are persistent,
+// so cloning a shard to publish it shares everything that didn't change
+#[derive(Clone)]
+pub struct CacheShard {
+    stock_info_map: HashMap<String, String>,
+    stock_history_map: HashMap<(String, usize), Vector<String>>,
+    session_map: HashMap<String, SessionStats>,
+    open_bars: HashMap<(String, usize), StockInformation>,
+    stock_sequences: HashMap<String, u64>,
+    log_watermark: u64,
+    symbol_memory: HashMap<String, SymbolMemory>,
+    memory_used: usize,
+}
+
+impl CacheShard {
+    pub fn new() -> Self {
+        CacheShard {
+            stock_info_map: HashMap::new(),
+            stock_history_map: HashMap::new(),
+            session_map: HashMap::new(),
+            open_bars: HashMap::new(),
+            stock_sequences: HashMap::new(),
+            log_watermark: 0,
+            symbol_memory: HashMap::new(),
+            memory_used: 0,
+        }
+    }
+
+    // Returns the json as it should be sent to subscribers and the bar this update finalized.
+    // update_clock orders the updates of all shards for eviction
+    pub fn add_stock_info(&mut self,
+                          stock_info: StockInformation,
+                          json_data: &str,
+                          price_precision: u32,
+                          session_reset_offset: i64,
+                          update_clock: u64) -> (String, Option<StockInformation>) {
+        let json_data:String = match stock_info.stock_interval {
+            1 => self.update_session(&stock_info, json_data, price_precision, session_reset_offset),
+            _ => json_data.to_string(),
+        };
+
+        if stock_info.volume_moved != 0 && stock_info.stock_interval == 1 || !self.stock_info_map.contains_key(&stock_info.stock_name) {
+            self.stock_info_map.insert(stock_info.stock_name.clone(), json_data.clone());
+        }
+
+        let key:(String, usize) = (stock_info.stock_name.clone(), stock_info.stock_interval);
+
+        if !self.stock_history_map.contains_key(&key) {
+            self.stock_history_map.insert(key.clone(), Vector::new());
+        }
+
+        let stock_history = self.stock_history_map.get_mut(&key).unwrap();
+
+        if stock_history.len() > MAX_HISTORY_LENGTH {
+            stock_history.pop_front();
+        }
+
+        stock_history.push_back(json_data.clone());
+
+        let finalized_bar = self.update_open_bar(key.clone(), stock_info);
+
+        self.account_memory(&key.0, Some(key.1));
+        self.symbol_memory.entry(key.0).or_insert_with(SymbolMemory::new).set_last_update(update_clock);
+
+        (json_data, finalized_bar)
+    }
+
+    // Merges bars loaded in bulk into a series and keeps the newest. A bar that is already there wins over a
+    // loaded one with the same timestamp. Sessions and open bars are left alone, the bars aren't live
+    pub fn load_history(&mut self, stock_name: &str, interval: usize, bars: Vec<(i64, String)>, update_clock: u64) {
+        let key:(String, usize) = (stock_name.to_string(), interval);
+
+        let mut merged:Vec<(i64, String)> = bars;
+
+        if let Some(stock_history) = self.stock_history_map.get(&key) {
+            merged.extend(stock_history.iter().map(|json_data| (stored_timestamp(json_data), json_data.clone())));
+        }
+
+        merged.sort_by_key(|(timestamp, _)| *timestamp);
+        merged.reverse();
+        merged.dedup_by_key(|(timestamp, _)| *timestamp);
+        merged.truncate(MAX_HISTORY_LENGTH + 1);
+        merged.reverse();
+
+        if !self.stock_info_map.contains_key(stock_name) {
+            if let Some((_, json_data)) = merged.last() {
+                self.stock_info_map.insert(stock_name.to_string(), json_data.clone());
+            }
+        }
+
+        self.stock_history_map.insert(key, merged.into_iter().map(|(_, json_data)| json_data).collect());
+
+        self.account_memory(stock_name, Some(interval));
+        self.symbol_memory.entry(stock_name.to_string()).or_insert_with(SymbolMemory::new).set_last_update(update_clock);
+    }
+
+    // Sequence of the last write ahead log record applied for a stock
+    pub fn stock_sequence(&self, stock_name: &str) -> u64 {
+        *self.stock_sequences.get(stock_name).unwrap_or(&0)
+    }
+
+    pub fn set_stock_sequence(&mut self, stock_name: &str, sequence: u64) {
+        self.stock_sequences.insert(stock_name.to_string(), sequence);
+        self.log_watermark = self.log_watermark.max(sequence);
+    }
+
+    // Every write ahead log record of this shard up to the watermark is contained in it
+    pub fn log_watermark(&self) -> u64 {
+        self.log_watermark
+    }
+
+    pub fn set_log_watermark(&mut self, sequence: u64) {
+        self.log_watermark = sequence;
+    }
+
+    // Estimated bytes held by every symbol of this shard
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+
+    pub fn symbol_memory(&self, stock_name: &str) -> Option<SymbolMemory> {
+        self.symbol_memory.get(stock_name).cloned()
+    }
+
+    // Name, last update and estimated bytes of every symbol
+    pub fn memory_usage(&self) -> Vec<(String, u64, usize)> {
+        self.symbol_memory.iter()
+            .map(|(stock_name, memory)| (stock_name.clone(), memory.last_update(), memory.bytes()))
+            .collect()
+    }
+
+    // Removes every series of a symbol unless it was updated after last_update. Returns the bytes freed
+    pub fn evict_symbol(&mut self, stock_name: &str, last_update: u64) -> Option<usize> {
+        let memory:SymbolMemory = match self.symbol_memory.get(stock_name) {
+            Some(v) if v.last_update() == last_update => v.clone(),
+            _ => return None,
+        };
+
+        for interval in memory.intervals().into_iter() {
+            let key:(String, usize) = (stock_name.to_string(), interval);
+
+            self.stock_history_map.remove(&key);
+            self.open_bars.remove(&key);
+        }
+
+        self.stock_info_map.remove(stock_name);
+        self.session_map.remove(stock_name);
+        self.stock_sequences.remove(stock_name);
+        self.symbol_memory.remove(stock_name);
+        self.memory_used -= memory.bytes();
+
+        Some(memory.bytes())
+    }
+
+    // Recomputes the estimate of a symbol, of its info, session and sequence and of one series if given
+    fn account_memory(&mut self, stock_name: &str, interval: Option<usize>) {
+        let other_bytes:usize = self.other_bytes(stock_name);
+        let series_bytes:Option<(usize, usize)> = interval.map(|interval| (interval, self.series_bytes(stock_name, interval)));
+
+        let memory = self.symbol_memory.entry(stock_name.to_string()).or_insert_with(SymbolMemory::new);
+        let bytes_before:usize = memory.bytes();
+
+        memory.set_other_bytes(other_bytes);
+
+        if let Some((interval, bytes)) = series_bytes {
+            memory.set_series_bytes(interval, bytes);
+        }
+
+        self.memory_used = self.memory_used + memory.bytes() - bytes_before;
+    }
+
+    fn other_bytes(&self, stock_name: &str) -> usize {
+        let entry_bytes:usize = stock_name.len() + ENTRY_OVERHEAD;
+
+        // The memory entry itself and the log sequence
+        let mut bytes:usize = 2 * entry_bytes + mem::size_of::<SymbolMemory>() + mem::size_of::<u64>();
+
+        if let Some(json_data) = self.stock_info_map.get(stock_name) {
+            bytes += entry_bytes + json_data.len();
+        }
+
+        if self.session_map.contains_key(stock_name) {
+            bytes += entry_bytes + mem::size_of::<SessionStats>();
+        }
+
+        bytes
+    }
+
+    fn series_bytes(&self, stock_name: &str, interval: usize) -> usize {
+        let key:(String, usize) = (stock_name.to_string(), interval);
+        let entry_bytes:usize = stock_name.len() + ENTRY_OVERHEAD;
+        let mut bytes:usize = 0;
+
+        if let Some(stock_history) = self.stock_history_map.get(&key) {
+            bytes += entry_bytes + stock_history.iter().map(|json_data| json_data.len() + ENTRY_OVERHEAD).sum::<usize>();
+        }
+
+        if self.open_bars.contains_key(&key) {
+            bytes += entry_bytes + mem::size_of::<StockInformation>();
+        }
+
+        bytes
+    }
+
+    // A bar is final as soon as a bar with a different timestamp arrives for the same series
+    fn update_open_bar(&mut self, key: (String, usize), stock_info: StockInformation) -> Option<StockInformation> {
+        let timestamp:i64 = stock_info.timestamp;
+
+        match self.open_bars.insert(key, stock_info) {
+            Some(v) if v.timestamp != timestamp => Some(v),
+            _ => None,
+        }
+    }
+
+    fn update_session(&mut self, stock_info: &StockInformation, json_data: &str, price_precision: u32, session_reset_offset: i64) -> String {
+        let session_id:i64 = (stock_info.timestamp - session_reset_offset).div_euclid(SESSION_LENGTH);
+
+        let session = self.session_map.entry(stock_info.stock_name.clone())
+            .or_insert_with(|| SessionStats::new(session_id));
+
+        if session.session_id() < session_id {
+            *session = SessionStats::new(session_id);
+        }
+
+        // Late bars of an earlier session don't count towards the current one, and its stats are gone
+        if session.session_id() > session_id {
+            return json_data.to_string();
+        }
+
+        if stock_info.volume_moved != 0 {
+            session.add_bar(stock_info);
+        }
+
+        append_json_fields(json_data, &session.to_json_fields(price_precision))
+    }
+
+    pub fn get_stock_names(&self) -> Vec<String> {
+        self.stock_history_map.keys()
+            .filter(|(_, interval)| *interval == 0)
+            .map(|(stock_name, _)| stock_name.clone())
+            .collect()
+    }
+
+    pub fn has_key(&self, key: &String) -> bool {
+        self.stock_info_map.contains_key(key)
+    }
+
+    pub fn get_series(&self) -> Vec<(String, usize)> {
+        self.stock_history_map.keys().cloned().collect()
+    }
+
+    // Bars of one series inside [from, to], the latest update wins if a bar was sent more than once
+    pub fn get_history_range(&self, stock_name: &str, interval: usize, from: i64, to: i64) -> Vec<(i64, String)> {
+        let stock_history = match self.stock_history_map.get(&(stock_name.to_string(), interval)) {
+            Some(v) => v,
+            None => return Vec::new(),
+        };
+
+        let mut bars:Vec<(i64, String)> = Vec::new();
+
+        for json_data in stock_history.iter() {
+            let timestamp:i64 = stored_timestamp(json_data);
+
+            if timestamp < from || timestamp > to { continue; }
+
+            match bars.last_mut() {
+                Some((last_timestamp, last_json)) if *last_timestamp == timestamp => *last_json = json_data.clone(),
+                _ => bars.push((timestamp, json_data.clone())),
+            }
+        }
+
+        bars
+    }
+
+    // Timestamp of the oldest bar of a series that is still held in memory
+    pub fn get_oldest_timestamp(&self, stock_name: &str, interval: usize) -> Option<i64> {
+        self.stock_history_map.get(&(stock_name.to_string(), interval))
+            .and_then(|stock_history| stock_history.front())
+            .map(|json_data| stored_timestamp(json_data))
+    }
+
+    pub fn dump_info(&self, cache_dump: &mut Vec<String>) {
+        for json_data in self.stock_info_map.values() {
+            cache_dump.push(json_data.to_string());
+        }
+    }
+
+    pub fn dump_history(&self, cache_dump: &mut Vec<String>) {
+        for stock_queue in self.stock_history_map.values() {
+            for json_data in stock_queue.iter() {
+                cache_dump.push(json_data.to_string());
+            }
+        }
+    }
+
+    pub fn write_snapshot(&self, snapshot: &mut String) {
+        for (stock_name, json_data) in self.stock_info_map.iter() {
+            snapshot.push_str(&format!("I\t{}\t{}\n", stock_name, single_line(json_data)));
+        }
+
+        for ((stock_name, interval), stock_queue) in self.stock_history_map.iter() {
+            for json_data in stock_queue.iter() {
+                snapshot.push_str(&format!("H\t{}\t{}\t{}\n", stock_name, interval, single_line(json_data)));
+            }
+        }
+
+        for (stock_name, session) in self.session_map.iter() {
+            snapshot.push_str(&format!("S\t{}\t{}\n", stock_name, session.to_snapshot_fields()));
+        }
+    }
+
+    // Restored symbols count as updated when they were restored, the eviction order of the last run is gone
+    pub fn restore_info(&mut self, stock_name: &str, json_data: &str, update_clock: u64) {
+        self.stock_info_map.insert(stock_name.to_string(), json_data.to_string());
+        self.account_memory(stock_name, None);
+        self.symbol_memory.entry(stock_name.to_string()).or_insert_with(SymbolMemory::new).set_last_update(update_clock);
+    }
+
+    pub fn restore_history(&mut self, stock_name: &str, interval: usize, json_data: &str, update_clock: u64) {
+        let key:(String, usize) = (stock_name.to_string(), interval);
+
+        if let Ok(stock_info) = decode_stock_info(json_data) {
+            self.open_bars.insert(key.clone(), stock_info);
+        }
+
+        self.stock_history_map.entry(key).or_default().push_back(json_data.to_string());
+        self.account_memory(stock_name, Some(interval));
+        self.symbol_memory.entry(stock_name.to_string()).or_insert_with(SymbolMemory::new).set_last_update(update_clock);
+    }
+
+    pub fn restore_session(&mut self, stock_name: &str, session: SessionStats, update_clock: u64) {
+        self.session_map.insert(stock_name.to_string(), session);
+        self.account_memory(stock_name, None);
+        self.symbol_memory.entry(stock_name.to_string()).or_insert_with(SymbolMemory::new).set_last_update(update_clock);
+    }
+}
+
+// Stored json was decoded once already, only entries of old snapshots may fail
+pub fn stored_timestamp(json_data: &str) -> i64 {
+    decode_stock_info(json_data).map(|stock_info| stock_info.timestamp).unwrap_or(0)
+}
+
+// Producers may send pretty printed json, newlines are only whitespace there
+fn single_line(json_data: &str) -> String {
+    json_data.replace(['\n', '\r'], " ")
+}
+
+pub fn append_json_fields(json_data: &str, fields: &str) -> String {
+    let trimmed = json_data.trim_end();
+
+    match trimmed.strip_suffix('}') {
+        Some(body) if body.trim_end().ends_with('{') => format!("{}{}}}", body, fields),
+        Some(body) => format!("{},{}}}", body, fields),
+        None => json_data.to_string(),
+    }
+}
\ No newline at end of file
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+use std::hash::{Hash, Hasher};
+use std::collections::hash_map::DefaultHasher;
+
+use crate::value_store::cache_shard::CacheShard;
+use crate::value_store::symbol_memory::SymbolMemory;
+
+const SNAPSHOT_HEADER: &str = "StockDatastore snapshot v";
+const SNAPSHOT_VERSION: u32 = 2;
+
+// Immutable point in time view of every shard. Readers keep it as long as they need,
+// writers publish a new one instead of changing it
+#[derive(Clone)]
+pub struct CacheView {
+    shards: Vec<Arc<CacheShard>>,
+}
+
+impl CacheView {
+    pub fn new(num_of_shards: usize) -> Self {
+        CacheView { shards: (0..num_of_shards).map(|_| Arc::new(CacheShard::new())).collect() }
+    }
+
+    pub fn from_shards(shards: Vec<Arc<CacheShard>>) -> Self {
+        CacheView { shards }
+    }
+
+    pub fn with_shard(&self, index: usize, shard: Arc<CacheShard>) -> Self {
+        let mut view = self.clone();
+        view.shards[index] = shard;
+
+        view
+    }
+
+    fn shard(&self, stock_name: &str) -> &CacheShard {
+        &self.shards[shard_index(stock_name, self.shards.len())]
+    }
+
+    pub fn get_stock_names(&self) -> String {
+        self.shards.iter()
+            .flat_map(|shard| shard.get_stock_names())
+            .fold(String::new(), |acc, stock| acc + &stock + "|")
+    }
+
+    pub fn has_key(&self, key: &String) -> bool {
+        self.shard(key).has_key(key)
+    }
+
+    // Symbol and interval of every series with history in memory
+    pub fn get_series(&self) -> Vec<(String, usize)> {
+        self.shards.iter().flat_map(|shard| shard.get_series()).collect()
+    }
+
+    pub fn get_history_range(&self, stock_name: &str, interval: usize, from: i64, to: i64) -> Vec<(i64, String)> {
+        self.shard(stock_name).get_history_range(stock_name, interval, from, to)
+    }
+
+    pub fn get_oldest_timestamp(&self, stock_name: &str, interval: usize) -> Option<i64> {
+        self.shard(stock_name).get_oldest_timestamp(stock_name, interval)
+    }
+
+    // Newest write ahead log record contained in any shard
+    pub fn log_watermark(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.log_watermark()).max().unwrap_or(0)
+    }
+
+    pub fn memory_used(&self) -> usize {
+        self.shards.iter().map(|shard| shard.memory_used()).sum()
+    }
+
+    pub fn memory_usage(&self) -> Vec<(String, u64, usize)> {
+        self.shards.iter().flat_map(|shard| shard.memory_usage()).collect()
+    }
+
+    pub fn symbol_memory(&self, stock_name: &str) -> Option<SymbolMemory> {
+        self.shard(stock_name).symbol_memory(stock_name)
+    }
+
+    pub fn get_entire_cache(&self) -> Vec<String> {
+        let mut cache_dump = Vec::<String>::new();
+
+        for shard in self.shards.iter() {
+            shard.dump_info(&mut cache_dump);
+        }
+
+        for shard in self.shards.iter() {
+            shard.dump_history(&mut cache_dump);
+        }
+
+        cache_dump
+    }
+
+    // One entry per line: Q (log watermark of the following entries), I (latest info), H (history) and S (session stats).
+    // Returns the snapshot and the log sequence up to which every record is contained in it
+    pub fn to_snapshot(&self) -> (String, u64) {
+        let mut snapshot = format!("{}{}\n", SNAPSHOT_HEADER, SNAPSHOT_VERSION);
+
+        for shard in self.shards.iter() {
+            snapshot.push_str(&format!("Q\t{}\n", shard.log_watermark()));
+            shard.write_snapshot(&mut snapshot);
+        }
+
+        let snapshot_sequence:u64 = self.shards.iter().map(|shard| shard.log_watermark()).min().unwrap_or(0);
+
+        (snapshot, snapshot_sequence)
+    }
+}
+
+// Version 1 kept the session price volume as a float
+pub fn snapshot_version(line: Option<&str>) -> Option<u32> {
+    match line?.strip_prefix(SNAPSHOT_HEADER)?.parse::<u32>() {
+        Ok(v @ 1..=SNAPSHOT_VERSION) => Some(v),
+        _ => None,
+    }
+}
+
+// Symbols are spread over the shards so updates to different symbols don't contend
+pub fn shard_index(stock_name: &str, num_of_shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    stock_name.hash(&mut hasher);
+
+    (hasher.finish() % num_of_shards as u64) as usize
+}
\ No newline at end of file
@@ -1 +1,5 @@
-pub mod stock_information_cache;
\ No newline at end of file
+pub mod cache_shard;
+pub mod cache_view;
+pub mod price;
+pub mod stock_information_cache;
+pub mod symbol_memory;
\ No newline at end of file
@@ -0,0 +1,294 @@
+use std::fmt;
+use std::collections::HashMap;
+
+use serde::{Deserialize, Deserializer};
+use serde::de::Error;
+use serde_json::value::RawValue;
+
+// Prices are rounded to this many decimals at most
+pub const MAX_PRICE_SCALE: u32 = 9;
+
+// Exact decimal price in units of 10^-scale. Json prices are parsed from the text the producer sent,
+// so 187.23 stays 187.23 instead of becoming the closest f64
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Price {
+    units: i64,
+    scale: u32,
+}
+
+impl Price {
+    pub fn new(units: i64, scale: u32) -> Result<Self, String> {
+        match scale {
+            0..=MAX_PRICE_SCALE => Ok(Price { units, scale }),
+            _ => Err(format!("Price scale {} is above {}", scale, MAX_PRICE_SCALE)),
+        }
+    }
+
+    pub fn units(self) -> i64 {
+        self.units
+    }
+
+    pub fn scale(self) -> u32 {
+        self.scale
+    }
+
+    // Decimal text with an optional exponent, e.g. "187.23", "-0.5" or "1.5e2".
+    // Digits beyond MAX_PRICE_SCALE are rounded
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid price {}", text);
+
+        let (negative, unsigned) = match text.strip_prefix('-') {
+            Some(v) => (true, v),
+            None => (false, text),
+        };
+
+        let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+            Some((mantissa, exponent)) => (mantissa, exponent.parse::<i32>().map_err(|_| invalid())?),
+            None => (unsigned, 0),
+        };
+
+        let (integer, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+
+        if integer.is_empty() || !integer.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+
+        let digits:String = format!("{}{}", integer.trim_start_matches('0'), fraction);
+
+        if digits.len() > 36 {
+            return Err(invalid());
+        }
+
+        let mut units:i128 = digits.parse::<i128>().unwrap_or(0);
+        let mut scale:i32 = (fraction.len() as i32).checked_sub(exponent).ok_or_else(invalid)?;
+
+        if scale < 0 {
+            units = units.checked_mul(10i128.checked_pow(scale.unsigned_abs()).ok_or_else(invalid)?).ok_or_else(invalid)?;
+            scale = 0;
+        }
+
+        if scale > MAX_PRICE_SCALE as i32 {
+            units = round_units(units, scale as u32 - MAX_PRICE_SCALE);
+            scale = MAX_PRICE_SCALE as i32;
+        }
+
+        let units:i128 = if negative { -units } else { units };
+
+        match i64::try_from(units) {
+            Ok(v) => Ok(Price { units: v, scale: scale as u32 }),
+            Err(_) => Err(format!("Price {} is out of range", text)),
+        }
+    }
+
+    // Units of the same price at another scale, rounded if the scale is smaller. Up to MAX_PRICE_SCALE they always fit
+    pub fn units_at(self, scale: u32) -> Option<i128> {
+        match scale >= self.scale {
+            true => (self.units as i128).checked_mul(10i128.checked_pow(scale - self.scale)?),
+            false => Some(self.round_to(scale).units as i128),
+        }
+    }
+
+    // Exact unless the product has more than MAX_PRICE_SCALE decimals, those are rounded. None if it doesn't fit
+    pub fn times(self, factor: Price) -> Option<Self> {
+        let units:i128 = (self.units as i128).checked_mul(factor.units as i128)?;
+        let scale:u32 = self.scale + factor.scale;
+
+        let (units, scale) = match scale > MAX_PRICE_SCALE {
+            true => (round_units(units, scale - MAX_PRICE_SCALE), MAX_PRICE_SCALE),
+            false => (units, scale),
+        };
+
+        Some(Price { units: i64::try_from(units).ok()?, scale })
+    }
+
+    // Rounded to MAX_PRICE_SCALE decimals, without trailing zeros. None for a zero divisor or if the quotient doesn't fit
+    pub fn divided_by(self, divisor: Price) -> Option<Self> {
+        if divisor.units == 0 {
+            return None;
+        }
+
+        let numerator:i128 = (self.units as i128).checked_mul(10i128.checked_pow(MAX_PRICE_SCALE + divisor.scale - self.scale)?)?;
+        let mut units:i128 = divide_rounded(numerator, divisor.units as i128);
+        let mut scale:u32 = MAX_PRICE_SCALE;
+
+        while scale > 0 && units % 10 == 0 {
+            units /= 10;
+            scale -= 1;
+        }
+
+        Some(Price { units: i64::try_from(units).ok()?, scale })
+    }
+
+    // numerator / denominator units of 10^-scale, rounded half away from zero. None for a zero denominator or if it doesn't fit
+    pub fn from_ratio(numerator: i128, denominator: i128, scale: u32) -> Option<Self> {
+        if denominator == 0 || scale > MAX_PRICE_SCALE {
+            return None;
+        }
+
+        Some(Price { units: i64::try_from(divide_rounded(numerator, denominator)).ok()?, scale })
+    }
+
+    // Rounds half away from zero if the price has more decimals than scale
+    pub fn round_to(self, scale: u32) -> Self {
+        match self.scale > scale {
+            true => Price { units: round_units(self.units as i128, self.scale - scale) as i64, scale },
+            false => self,
+        }
+    }
+}
+
+fn divide_rounded(numerator: i128, denominator: i128) -> i128 {
+    let mut quotient:i128 = numerator / denominator;
+
+    if (numerator % denominator).unsigned_abs() * 2 >= denominator.unsigned_abs() {
+        quotient += if (numerator < 0) == (denominator < 0) { 1 } else { -1 };
+    }
+
+    quotient
+}
+
+// Dropping more than 38 digits leaves less than half a unit of any i128, that rounds to zero
+fn round_units(units: i128, digits: u32) -> i128 {
+    let divisor:u128 = match 10u128.checked_pow(digits) {
+        Some(v) => v,
+        None => return 0,
+    };
+
+    let rounded:i128 = ((units.unsigned_abs() + divisor / 2) / divisor) as i128;
+
+    if units < 0 { -rounded } else { rounded }
+}
+
+// Always as many decimals as the scale, so the text is the exact value
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign:&str = if self.units < 0 { "-" } else { "" };
+        let units:u64 = self.units.unsigned_abs();
+
+        match self.scale {
+            0 => write!(f, "{}{}", sign, units),
+            scale => {
+                let divisor:u64 = 10u64.pow(scale);
+
+                write!(f, "{}{}.{:0width$}", sign, units / divisor, units % divisor, width = scale as usize)
+            },
+        }
+    }
+}
+
+// Numbers or strings. The raw json text is read instead of an f64, binary updates that went through
+// a json value only have the f64 left, strings keep them exact there too
+impl<'de> Deserialize<'de> for Price {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw:Box<RawValue> = Box::deserialize(deserializer)?;
+
+        let text:String = match raw.get().starts_with('"') {
+            true => serde_json::from_str::<String>(raw.get()).map_err(D::Error::custom)?,
+            false => raw.get().to_string(),
+        };
+
+        Price::parse(text.trim()).map_err(D::Error::custom)
+    }
+}
+
+// Decimals prices of a symbol are quoted with, e.g. 2 for most stocks and 5 for currency pairs
+#[derive(Clone)]
+pub struct TickPrecision {
+    default_precision: u32,
+    symbols: HashMap<String, u32>,
+}
+
+impl TickPrecision {
+    pub fn new(default_precision: u32, symbols: Vec<(String, u32)>) -> Self {
+        TickPrecision {
+            default_precision: default_precision.min(MAX_PRICE_SCALE),
+            symbols: symbols.into_iter().map(|(stock_name, precision)| (stock_name, precision.min(MAX_PRICE_SCALE))).collect(),
+        }
+    }
+
+    pub fn of(&self, stock_name: &str) -> u32 {
+        *self.symbols.get(stock_name).unwrap_or(&self.default_precision)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn price(text: &str) -> Price {
+        Price::parse(text).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(price("187.23"), Price { units: 18723, scale: 2 });
+        assert_eq!(price("-0.5"), Price { units: -5, scale: 1 });
+        assert_eq!(price("007"), Price { units: 7, scale: 0 });
+        assert_eq!(price("1.5e2"), Price { units: 150, scale: 0 });
+        assert_eq!(price("1.5E-2"), Price { units: 15, scale: 3 });
+        assert_eq!(price("0.1234567895"), Price { units: 123456790, scale: 9 });
+        assert_eq!(price("-0.1234567895"), Price { units: -123456790, scale: 9 });
+        assert_eq!(price("187.23").to_string(), "187.23");
+        assert_eq!(price("-0.05").to_string(), "-0.05");
+
+        for text in ["", "-", ".5", "1.2.3", "1e", "abc", "1,5", "+1", "9223372036854775808", "1234567890123456789012345678901234567"] {
+            assert!(Price::parse(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parse_extreme_exponents() {
+        assert_eq!(price("1e-60"), Price { units: 0, scale: 9 });
+        assert_eq!(price("1e-200"), Price { units: 0, scale: 9 });
+        assert_eq!(price("-9.99e-39"), Price { units: 0, scale: 9 });
+        assert_eq!(price("5e-10"), Price { units: 1, scale: 9 });
+        assert_eq!(price("1e-2147483647"), Price { units: 0, scale: 9 });
+        assert!(Price::parse("1e-2147483648").is_err());
+        assert_eq!(price("1e18"), Price { units: 1_000_000_000_000_000_000, scale: 0 });
+        assert!(Price::parse("1e19").is_err());
+        assert!(Price::parse("1e60").is_err());
+        assert!(Price::parse("1e2147483647").is_err());
+        assert!(Price::parse("1e99999999999").is_err());
+        assert!(Price::parse("0.1e-2147483647").is_err());
+    }
+
+    #[test]
+    fn round_to() {
+        assert_eq!(price("187.235").round_to(2), price("187.24"));
+        assert_eq!(price("-187.235").round_to(2), price("-187.24"));
+        assert_eq!(price("187.234").round_to(2), price("187.23"));
+        assert_eq!(price("187.2").round_to(4), price("187.2"));
+        assert_eq!(price("0.5").round_to(0), price("1"));
+        assert_eq!(round_units(i128::MAX, 38), 2);
+        assert_eq!(round_units(i128::MIN, 39), 0);
+        assert_eq!(round_units(-15, u32::MAX), 0);
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(price("1.5").times(price("2")), Some(price("3.0")));
+        assert_eq!(price("-0.01").times(price("0.01")), Some(price("-0.0001")));
+        assert_eq!(price("0.123456789").times(price("0.5")), Some(price("0.061728395")));
+        assert_eq!(price("0.000000001").times(price("0.000000001")), Some(price("0.000000000")));
+        assert_eq!(price("9223372036854775807").times(price("2")), None);
+    }
+
+    #[test]
+    fn divided_by() {
+        assert_eq!(price("187.23").divided_by(price("100")), Some(price("1.8723")));
+        assert_eq!(price("1").divided_by(price("3")), Some(price("0.333333333")));
+        assert_eq!(price("-2").divided_by(price("3")), Some(price("-0.666666667")));
+        assert_eq!(price("1700000000000").divided_by(price("1000")), Some(price("1700000000")));
+        assert_eq!(price("1").divided_by(price("0")), None);
+        assert_eq!(price("9223372036854775807").divided_by(price("0.5")), None);
+    }
+
+    #[test]
+    fn units_at() {
+        assert_eq!(price("187.23").units_at(4), Some(1872300));
+        assert_eq!(price("187.235").units_at(2), Some(18724));
+        assert_eq!(price("92233720.36854775807").units_at(9), Some(92233720368547758));
+        assert_eq!(price("9223372036854775807").units_at(9), Some(9223372036854775807000000000));
+        assert_eq!(price("9223372036854775807").units_at(40), None);
+    }
+}
\ No newline at end of file
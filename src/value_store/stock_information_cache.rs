@@ -1,18 +1,49 @@
-use std::collections::HashMap;
-use std::collections::VecDeque;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 
+use arc_swap::ArcSwap;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+
+use crate::events::event_bus::EventBus;
+use crate::events::update_event::UpdateEvent;
+use crate::protocol::json_codec::{decode_stock_info, json_string};
+use crate::value_store::cache_shard::{CacheShard, append_json_fields};
+use crate::value_store::cache_view::{CacheView, shard_index, snapshot_version};
+use crate::value_store::price::{MAX_PRICE_SCALE, Price, TickPrecision};
+
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
 pub struct StockInformation {
+    #[serde(rename = "sn")]
     pub stock_name: String,
+    #[serde(rename = "si")]
     pub stock_interval: usize,
+    #[serde(rename = "t")]
     pub timestamp:i64,
 
-    pub avg_price: f64,
-    pub avg_price_open: f64,
-    pub min_price: f64,
-    pub max_price: f64,
+    #[serde(rename = "ap")]
+    pub avg_price: Price,
+    #[serde(rename = "op")]
+    pub avg_price_open: Price,
+    #[serde(rename = "mn")]
+    pub min_price: Price,
+    #[serde(rename = "mx")]
+    pub max_price: Price,
 
+    #[serde(rename = "vm")]
     pub volume_moved: i64,
+    #[serde(rename = "nt")]
     pub num_of_trades: i64,
+
+    #[serde(rename = "sw")]
+    pub session_vwap: Price,
+    #[serde(rename = "sv")]
+    pub session_volume: i64,
+    #[serde(rename = "st")]
+    pub session_trades: i64,
 }
 
 impl StockInformation {
@@ -21,135 +52,547 @@ impl StockInformation {
             stock_name: String::new(),
             stock_interval: 0,
             timestamp: 0,
-            avg_price: 0.0,
-            avg_price_open: 0.0,
-            min_price: 0.0,
-            max_price: 0.0,
+            avg_price: Price::default(),
+            avg_price_open: Price::default(),
+            min_price: Price::default(),
+            max_price: Price::default(),
             volume_moved: 0,
-            num_of_trades: 0
+            num_of_trades: 0,
+            session_vwap: Price::default(),
+            session_volume: 0,
+            session_trades: 0,
         }
     }
 
-    pub fn clone(&self) -> Self {
-        StockInformation {
-            stock_name: self.stock_name.clone(),
-            stock_interval: self.stock_interval,
-            timestamp: self.timestamp,
-            avg_price: self.avg_price,
-            avg_price_open: self.avg_price_open,
-            min_price: self.min_price,
-            max_price: self.max_price,
-            volume_moved: self.volume_moved,
-            num_of_trades: self.num_of_trades
-        }
+    pub fn to_json(&self) -> String {
+        format!("{{\"sn\":{},\"si\":{},\"t\":{},\"ap\":{},\"op\":{},\"mn\":{},\"mx\":{},\"vm\":{},\"nt\":{}}}",
+            json_string(&self.stock_name), self.stock_interval, self.timestamp,
+            self.avg_price, self.avg_price_open, self.min_price, self.max_price,
+            self.volume_moved, self.num_of_trades)
+    }
+}
+
+// price_volume sums price times volume in units of 10^-MAX_PRICE_SCALE, so the vwap is only rounded once
+#[derive(Clone)]
+pub struct SessionStats {
+    session_id: i64,
+    price_volume: i128,
+    volume: i64,
+    trades: i64,
+}
+
+impl SessionStats {
+    pub fn new(session_id: i64) -> Self {
+        SessionStats { session_id, price_volume: 0, volume: 0, trades: 0 }
+    }
+
+    pub fn add_bar(&mut self, stock_info: &StockInformation) {
+        let price_units:i128 = stock_info.avg_price.units() as i128 * 10i128.pow(MAX_PRICE_SCALE - stock_info.avg_price.scale());
+
+        self.price_volume = self.price_volume.saturating_add(price_units.saturating_mul(stock_info.volume_moved as i128));
+        self.volume += stock_info.volume_moved;
+        self.trades += stock_info.num_of_trades;
+    }
+
+    pub fn session_id(&self) -> i64 {
+        self.session_id
+    }
+
+    // Rounded to the tick precision of the symbol, zero without volume
+    pub fn vwap(&self, price_precision: u32) -> Price {
+        let precision:u32 = price_precision.min(MAX_PRICE_SCALE);
+        let denominator:i128 = self.volume as i128 * 10i128.pow(MAX_PRICE_SCALE - precision);
+
+        Price::from_ratio(self.price_volume, denominator, precision).unwrap_or_default()
+    }
+
+    pub fn to_json_fields(&self, price_precision: u32) -> String {
+        let vwap:Price = self.vwap(price_precision);
+
+        format!("\"sw\":{},\"sv\":{},\"st\":{}", vwap, self.volume, self.trades)
     }
 
-    pub fn insert_data(&mut self, key: String, value: String) {
-        match key.as_str() {
-            "sn" => self.stock_name = value,
-            "si" => self.stock_interval = value.parse::<usize>().unwrap(),
-            "ap" => self.avg_price = value.parse::<f64>().unwrap(),
-            "op" => self.avg_price_open = value.parse::<f64>().unwrap(),
-            "mn" => self.min_price = value.parse::<f64>().unwrap(),
-            "mx" => self.max_price = value.parse::<f64>().unwrap(),
-            "vm" => self.volume_moved = value.parse::<i64>().unwrap(),
-            "nt" => self.num_of_trades = value.parse::<i64>().unwrap(),
-            "t" => self.timestamp = value.parse::<i64>().unwrap(),
-            _ => (),
+    pub fn to_snapshot_fields(&self) -> String {
+        format!("{}\t{}\t{}\t{}", self.session_id, self.price_volume, self.volume, self.trades)
+    }
+
+    pub fn from_snapshot_fields(fields: &[&str], version: u32) -> Option<Self> {
+        match fields {
+            [session_id, price_volume, volume, trades] => Some(SessionStats {
+                session_id: session_id.parse::<i64>().ok()?,
+                price_volume: match version {
+                    1 => (price_volume.parse::<f64>().ok()? * 10f64.powi(MAX_PRICE_SCALE as i32)).round() as i128,
+                    _ => price_volume.parse::<i128>().ok()?,
+                },
+                volume: volume.parse::<i64>().ok()?,
+                trades: trades.parse::<i64>().ok()?,
+            }),
+            _ => None,
         }
     }
 }
 
+
 pub struct StockInformationCache {
-    stock_info_map: HashMap<String, String>,
-    stock_history_map: HashMap<(String, usize), VecDeque<String>>,
+    shards: Vec<Mutex<CacheShard>>,
+    view: ArcSwap<CacheView>,
+    session_reset_offset: i64,
+    tick_precision: TickPrecision,
+    event_bus: Arc<EventBus>,
+    memory_budget: usize,
+    configured_symbols: HashSet<String>,
+    update_clock: AtomicU64,
+    eviction_lock: Mutex<()>,
+    evicted_symbols: AtomicUsize,
+    evicted_bytes: AtomicUsize,
+    recently_evicted: Mutex<VecDeque<String>>,
 }
 
+const RECENTLY_EVICTED_SIZE: usize = 100;
+
 impl StockInformationCache {
-    pub fn new() -> Self {
-        StockInformationCache{ stock_info_map:HashMap::new(), stock_history_map:HashMap::new() }
+    // A memory_budget of 0 never evicts. Configured symbols are only evicted once no other symbol is left.
+    // Finalized bars are published to the event bus
+    pub fn new(session_reset_offset: i64,
+               num_of_shards: usize,
+               memory_budget: usize,
+               configured_symbols: Vec<String>,
+               tick_precision: TickPrecision,
+               event_bus: Arc<EventBus>) -> Self {
+        let num_of_shards:usize = num_of_shards.max(1);
+
+        StockInformationCache{
+            shards: (0..num_of_shards).map(|_| Mutex::new(CacheShard::new())).collect(),
+            view: ArcSwap::from_pointee(CacheView::new(num_of_shards)),
+            session_reset_offset,
+            tick_precision,
+            event_bus,
+            memory_budget,
+            configured_symbols: configured_symbols.into_iter().collect(),
+            update_clock: AtomicU64::new(0),
+            eviction_lock: Mutex::new(()),
+            evicted_symbols: AtomicUsize::new(0),
+            evicted_bytes: AtomicUsize::new(0),
+            recently_evicted: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // Readers get an immutable point in time view and never wait on writers
+    pub fn view(&self) -> Arc<CacheView> {
+        self.view.load_full()
+    }
+
+    // Replaces one shard in the published view, updates of other shards are retried on top of it
+    fn publish(&self, index: usize, shard: &CacheShard) -> Arc<CacheShard> {
+        let shard = Arc::new(shard.clone());
+
+        self.view.rcu(|view| view.with_shard(index, shard.clone()));
+
+        shard
+    }
+
+    // Returns the stock name and the json as it should be sent to subscribers
+    pub fn add_json(&self, stock_info: StockInformation, json_data: &str) -> (String, String) {
+        let (stock_info, rounded_json) = self.round_prices(stock_info, json_data);
+        let json_data:&str = rounded_json.as_deref().unwrap_or(json_data);
+        let price_precision:u32 = self.tick_precision.of(&stock_info.stock_name);
+        let stock_name:String = stock_info.stock_name.clone();
+        let index:usize = shard_index(&stock_name, self.shards.len());
+
+        let (json_data, finalized_bar) = {
+            let mut shard = self.shards[index].lock().unwrap();
+
+            let update = shard.add_stock_info(stock_info, json_data, price_precision, self.session_reset_offset, self.next_update());
+            self.publish(index, &shard);
+
+            update
+        };
+
+        self.send_finalized_bar(finalized_bar);
+        self.enforce_memory_budget();
+
+        (stock_name, json_data)
+    }
+
+    // The shard stays locked while logging, so records of one stock are logged in the order they are applied
+    pub fn add_logged_json<F>(&self, stock_info: StockInformation, json_data: &str, append_to_log: F) -> io::Result<(String, String)>
+    where F: FnOnce(&str) -> io::Result<u64> {
+        let (stock_info, rounded_json) = self.round_prices(stock_info, json_data);
+        let json_data:&str = rounded_json.as_deref().unwrap_or(json_data);
+        let price_precision:u32 = self.tick_precision.of(&stock_info.stock_name);
+        let stock_name:String = stock_info.stock_name.clone();
+        let index:usize = shard_index(&stock_name, self.shards.len());
+
+        let (json_data, finalized_bar) = {
+            let mut shard = self.shards[index].lock().unwrap();
+
+            let sequence:u64 = append_to_log(json_data)?;
+            shard.set_stock_sequence(&stock_name, sequence);
+
+            let update = shard.add_stock_info(stock_info, json_data, price_precision, self.session_reset_offset, self.next_update());
+            self.publish(index, &shard);
+
+            update
+        };
+
+        self.send_finalized_bar(finalized_bar);
+        self.enforce_memory_budget();
+
+        Ok((stock_name, json_data))
+    }
+
+    // Historical bars are put into the history of their series only. They aren't logged, published
+    // or counted as live updates. Returns the bars with prices rounded to the tick precision
+    pub fn load_bars(&self, bars: Vec<StockInformation>) -> Vec<StockInformation> {
+        let mut series:HashMap<(String, usize), Vec<(i64, String)>> = HashMap::new();
+        let mut rounded_bars:Vec<StockInformation> = Vec::with_capacity(bars.len());
+
+        for mut stock_info in bars.into_iter() {
+            let price_precision:u32 = self.tick_precision.of(&stock_info.stock_name);
+
+            for price in [&mut stock_info.avg_price, &mut stock_info.avg_price_open, &mut stock_info.min_price, &mut stock_info.max_price] {
+                *price = price.round_to(price_precision);
+            }
+
+            series.entry((stock_info.stock_name.clone(), stock_info.stock_interval))
+                .or_default()
+                .push((stock_info.timestamp, stock_info.to_json()));
+
+            rounded_bars.push(stock_info);
+        }
+
+        for ((stock_name, interval), bars) in series.into_iter() {
+            let index:usize = shard_index(&stock_name, self.shards.len());
+            let mut shard = self.shards[index].lock().unwrap();
+
+            shard.load_history(&stock_name, interval, bars, self.next_update());
+            self.publish(index, &shard);
+        }
+
+        self.enforce_memory_budget();
+
+        rounded_bars
+    }
+
+    // Applies a write ahead log record unless the restored snapshot already contains it
+    pub fn replay_json(&self, json_data: &str, sequence: u64) -> bool {
+        let stock_info:StockInformation = match decode_stock_info(json_data) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("Skipping write ahead log record {}: {}", sequence, e);
+
+                return false;
+            },
+        };
+
+        let (stock_info, rounded_json) = self.round_prices(stock_info, json_data);
+        let json_data:&str = rounded_json.as_deref().unwrap_or(json_data);
+        let price_precision:u32 = self.tick_precision.of(&stock_info.stock_name);
+        let stock_name:String = stock_info.stock_name.clone();
+        let index:usize = shard_index(&stock_name, self.shards.len());
+
+        let finalized_bar = {
+            let mut shard = self.shards[index].lock().unwrap();
+
+            if sequence <= shard.stock_sequence(&stock_name) {
+                return false;
+            }
+
+            shard.set_stock_sequence(&stock_name, sequence);
+
+            let (_, finalized_bar) = shard.add_stock_info(stock_info, json_data, price_precision, self.session_reset_offset, self.next_update());
+            self.publish(index, &shard);
+
+            finalized_bar
+        };
+
+        self.send_finalized_bar(finalized_bar);
+        self.enforce_memory_budget();
+
+        true
+    }
+
+    // Prices with more decimals than the tick precision of the symbol are rounded. Only then the json is
+    // written again, with every other field kept as it was sent
+    fn round_prices(&self, mut stock_info: StockInformation, json_data: &str) -> (StockInformation, Option<String>) {
+        let price_precision:u32 = self.tick_precision.of(&stock_info.stock_name);
+        let mut rounded:Vec<(&str, Price)> = Vec::new();
+
+        for (key, price) in [
+            ("ap", &mut stock_info.avg_price),
+            ("op", &mut stock_info.avg_price_open),
+            ("mn", &mut stock_info.min_price),
+            ("mx", &mut stock_info.max_price),
+            ("sw", &mut stock_info.session_vwap),
+        ] {
+            if price.scale() > price_precision {
+                *price = price.round_to(price_precision);
+                rounded.push((key, *price));
+            }
+        }
+
+        if rounded.is_empty() {
+            return (stock_info, None);
+        }
+
+        let mut fields:BTreeMap<String, Box<RawValue>> = match serde_json::from_str(json_data) {
+            Ok(v) => v,
+            Err(_) => return (stock_info, None),
+        };
+
+        for (key, _) in rounded.iter() {
+            fields.remove(*key);
+        }
+
+        let price_fields:Vec<String> = rounded.iter().map(|(key, price)| format!("\"{}\":{}", key, price)).collect();
+        let json_data:String = append_json_fields(&serde_json::to_string(&fields).unwrap(), &price_fields.join(","));
+
+        (stock_info, Some(json_data))
     }
 
-    pub fn add_json(&mut self, json_data: &str) -> String {
-        let stock_info:StockInformation = parse_json_to_stock_info(json_data);
+    fn next_update(&self) -> u64 {
+        self.update_clock.fetch_add(1, Ordering::Relaxed) + 1
+    }
 
-        if stock_info.volume_moved != 0 && stock_info.stock_interval == 1 || !self.stock_info_map.contains_key(&stock_info.stock_name) {
-            self.stock_info_map.insert(stock_info.stock_name.clone(), json_data.to_string());
+    // Evicts the least recently updated symbols until the cache is back at 90% of its budget,
+    // symbols that aren't in the stock list go first. Only one caller evicts at a time
+    fn enforce_memory_budget(&self) {
+        if self.memory_budget == 0 || self.view().memory_used() <= self.memory_budget {
+            return;
         }
 
-        let key:(String, usize) = (stock_info.stock_name.clone(), stock_info.stock_interval);
+        let _eviction = match self.eviction_lock.try_lock() {
+            Ok(v) => v,
+            Err(_) => return,
+        };
+
+        let target:usize = self.memory_budget / 10 * 9;
+        let mut memory_used:usize = self.view().memory_used();
+
+        let mut candidates:Vec<(bool, u64, String)> = self.view().memory_usage().into_iter()
+            .map(|(stock_name, last_update, _)| (self.configured_symbols.contains(&stock_name), last_update, stock_name))
+            .collect();
+
+        candidates.sort_unstable();
 
-        if !self.stock_history_map.contains_key(&key) {
-            self.stock_history_map.insert(key.clone(), VecDeque::new());
+        let mut evicted:Vec<String> = Vec::new();
+        let mut evicted_bytes:usize = 0;
+
+        for (_, last_update, stock_name) in candidates.into_iter() {
+            if memory_used <= target {
+                break;
+            }
+
+            let index:usize = shard_index(&stock_name, self.shards.len());
+            let mut shard = self.shards[index].lock().unwrap();
+
+            // Skipped if it was updated since the candidates were collected
+            let freed:usize = match shard.evict_symbol(&stock_name, last_update) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            self.publish(index, &shard);
+
+            memory_used = memory_used.saturating_sub(freed);
+            evicted_bytes += freed;
+            evicted.push(stock_name);
+        }
+
+        if evicted.is_empty() {
+            return;
         }
 
-        let stock_history = self.stock_history_map.get_mut(&key).unwrap();
+        println!("Memory budget of {} bytes exceeded. Evicted {} symbols, {} bytes", self.memory_budget, evicted.len(), evicted_bytes);
+
+        self.evicted_symbols.fetch_add(evicted.len(), Ordering::Relaxed);
+        self.evicted_bytes.fetch_add(evicted_bytes, Ordering::Relaxed);
 
-        if stock_history.len() > 120 { 
-            stock_history.pop_front(); 
+        let mut recently_evicted = self.recently_evicted.lock().unwrap();
+
+        for stock_name in evicted.into_iter() {
+            if recently_evicted.len() >= RECENTLY_EVICTED_SIZE {
+                recently_evicted.pop_front();
+            }
+
+            recently_evicted.push_back(stock_name);
         }
+    }
 
-        stock_history.push_back(json_data.to_string());
+    pub fn memory_stats(&self) -> String {
+        let view = self.view();
+        let recently_evicted:Vec<String> = self.recently_evicted.lock().unwrap().iter()
+            .map(|stock_name| json_string(stock_name))
+            .collect();
 
-        key.0
+        format!("{{\"stats\":\"memory\",\"budget\":{},\"used\":{},\"symbols\":{},\"evicted_symbols\":{},\"evicted_bytes\":{},\"recently_evicted\":[{}]}}",
+            self.memory_budget, view.memory_used(), view.memory_usage().len(),
+            self.evicted_symbols.load(Ordering::Relaxed), self.evicted_bytes.load(Ordering::Relaxed),
+            recently_evicted.join(","))
+    }
+
+    pub fn symbol_memory_stats(&self, stock_name: &str) -> String {
+        match self.view().symbol_memory(stock_name) {
+            Some(memory) => memory.to_json(stock_name),
+            None => format!("{{\"stats\":\"memory\",\"sn\":{},\"error\":\"Unknown stock\"}}", json_string(stock_name)),
+        }
+    }
+
+    fn send_finalized_bar(&self, finalized_bar: Option<StockInformation>) {
+        if let Some(v) = finalized_bar {
+            self.event_bus.publish(UpdateEvent::BarFinalized(v));
+        }
     }
 
     pub fn get_stock_names(&self) -> String {
-        self.stock_history_map.keys()
-            .filter(|(stock_name, interval)| *interval == 0)
-            .map(|(stock_name, interval)| stock_name)
-            .fold(String::new(), |acc, stock| acc + stock + "|")
+        self.view().get_stock_names()
     }
 
     pub fn has_key(&self, key: &String) -> bool {
-        self.stock_info_map.contains_key(key)
+        self.view().has_key(key)
     }
 
     pub fn get_entire_cache(&self) -> Vec<String> {
-        let mut cache_dump = Vec::<String>::new();
+        self.view().get_entire_cache()
+    }
 
-        for json_data in self.stock_info_map.values().map(|a| a.to_string()).into_iter() {
-            cache_dump.push(json_data);
-        }
+    // Idle shards have nothing new in the log, but their watermark has to move for the log to be compacted.
+    // Each shard is only locked for stamping, the snapshot is written from the copies taken under that lock.
+    // The published view may already hold updates after the watermark, which replay would apply twice
+    pub fn to_snapshot<F: Fn() -> u64>(&self, current_sequence: F) -> (String, u64) {
+        let shards:Vec<Arc<CacheShard>> = self.shards.iter().enumerate()
+            .map(|(index, shard)| {
+                let mut shard = shard.lock().unwrap();
+
+                shard.set_log_watermark(current_sequence());
+                self.publish(index, &shard)
+            })
+            .collect();
+
+        CacheView::from_shards(shards).to_snapshot()
+    }
+
+    pub fn restore_snapshot(&self, snapshot: &str) -> Result<usize, String> {
+        let mut lines = snapshot.lines();
+
+        let version:u32 = match snapshot_version(lines.next()) {
+            Some(v) => v,
+            None => return Err("Unknown snapshot header".to_string()),
+        };
+
+        let mut restored:usize = 0;
+        let mut sequence:u64 = 0;
 
-        for stock_queue in self.stock_history_map.values().into_iter() {
-            for json_data in stock_queue.clone().into_iter() {
-                cache_dump.push(json_data);
+        for line in lines {
+            let (tag, entry) = match line.split_once('\t') {
+                Some(v) => v,
+                None => return Err(format!("Invalid snapshot line {}", line)),
+            };
+
+            let fields:Vec<&str> = match tag {
+                "I" => entry.splitn(2, '\t').collect(),
+                "H" => entry.splitn(3, '\t').collect(),
+                _ => entry.split('\t').collect(),
+            };
+
+            let stock_name:&str = match (tag, &fields[..]) {
+                ("Q", [v]) => {
+                    sequence = match v.parse::<u64>() {
+                        Ok(v) => v,
+                        Err(_) => return Err(format!("Invalid sequence in line {}", line)),
+                    };
+
+                    continue;
+                },
+                (_, [stock_name, ..]) => stock_name,
+                _ => return Err(format!("Invalid snapshot line {}", line)),
+            };
+
+            let mut shard = self.shards[shard_index(stock_name, self.shards.len())].lock().unwrap();
+
+            match (tag, &fields[..]) {
+                ("I", [_, json_data]) => shard.restore_info(stock_name, json_data, self.next_update()),
+                ("H", [_, interval, json_data]) => {
+                    let interval = match interval.parse::<usize>() {
+                        Ok(v) => v,
+                        Err(_) => return Err(format!("Invalid interval in line {}", line)),
+                    };
+
+                    shard.restore_history(stock_name, interval, json_data, self.next_update());
+                },
+                ("S", [_, session_fields @ ..]) => {
+                    match SessionStats::from_snapshot_fields(session_fields, version) {
+                        Some(session) => shard.restore_session(stock_name, session, self.next_update()),
+                        None => return Err(format!("Invalid session in line {}", line)),
+                    }
+                },
+                _ => return Err(format!("Invalid snapshot line {}", line)),
             }
+
+            shard.set_stock_sequence(stock_name, sequence);
+            restored += 1;
+        }
+
+        for (index, shard) in self.shards.iter().enumerate() {
+            self.publish(index, &shard.lock().unwrap());
         }
 
-        cache_dump
+        Ok(restored)
     }
 }
 
-pub fn parse_json_to_stock_info(json_data: &str) -> StockInformation {
-    let mut tmp: String = String::new();
-    let mut key: String = String::new();
-    let mut stock_info = StockInformation::new();
-
-    for p in json_data.chars() {
-        if p == ' ' || p == '\n' || p == '\t' || p == '\"' || p == '{' || p == '}' { 
-            continue; 
-        }
-        
-        if p == ':' || p == ',' {
-            match key.len() {
-                0 => key = tmp,
-                _ => {
-                    stock_info.insert_data(key, tmp);
-                    key = String::new();
-                }
-            }
-            
-            tmp = String::new();
-            
-            continue;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(avg_price: &str, volume_moved: i64) -> StockInformation {
+        StockInformation { avg_price: Price::parse(avg_price).unwrap(), volume_moved, num_of_trades: 1, ..StockInformation::new() }
+    }
+
+    #[test]
+    fn session_vwap_is_exact() {
+        let mut session = SessionStats::new(1);
+
+        for _ in 0..3 {
+            session.add_bar(&bar("0.1", 1));
         }
 
-        tmp.push(p);
+        session.add_bar(&bar("0.2", 3));
+
+        assert_eq!(session.vwap(9).to_string(), "0.150000000");
+        assert_eq!(session.vwap(2).to_string(), "0.15");
+        assert_eq!(session.to_json_fields(2), "\"sw\":0.15,\"sv\":6,\"st\":4");
+        assert_eq!(SessionStats::new(1).vwap(2), Price::default());
     }
 
-    stock_info.insert_data(key, tmp);
+    #[test]
+    fn session_vwap_rounds_half_away_from_zero() {
+        let mut session = SessionStats::new(1);
+        session.add_bar(&bar("1.005", 1));
+
+        assert_eq!(session.vwap(2).to_string(), "1.01");
 
-    stock_info
+        session.add_bar(&bar("1", 2));
+
+        assert_eq!(session.vwap(3).to_string(), "1.002");
+    }
+
+    #[test]
+    fn session_snapshot_fields() {
+        let mut session = SessionStats::new(7);
+        session.add_bar(&bar("187.23", 100));
+
+        let fields:String = session.to_snapshot_fields();
+
+        assert_eq!(fields, "7\t18723000000000\t100\t1");
+
+        let restored = SessionStats::from_snapshot_fields(&fields.split('\t').collect::<Vec<&str>>(), 2).unwrap();
+
+        assert_eq!(restored.to_json_fields(2), session.to_json_fields(2));
+
+        let old = SessionStats::from_snapshot_fields(&["7", "18723", "100", "1"], 1).unwrap();
+
+        assert_eq!(old.vwap(2).to_string(), "187.23");
+        assert!(SessionStats::from_snapshot_fields(&["7", "18723.5", "100", "1"], 2).is_none());
+    }
 }
\ No newline at end of file
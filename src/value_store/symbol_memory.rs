@@ -0,0 +1,57 @@
+use im::HashMap;
+
+use crate::protocol::json_codec::json_string;
+
+// Rough cost of a map or vector entry on top of the data it holds
+pub const ENTRY_OVERHEAD: usize = 64;
+
+// Estimated bytes one symbol holds in its shard, per series and for its latest info,
+// session and log sequence, and the update clock of its last update
+#[derive(Clone)]
+pub struct SymbolMemory {
+    series_bytes: HashMap<usize, usize>,
+    other_bytes: usize,
+    last_update: u64,
+}
+
+impl SymbolMemory {
+    pub fn new() -> Self {
+        SymbolMemory { series_bytes: HashMap::new(), other_bytes: 0, last_update: 0 }
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.other_bytes + self.series_bytes.values().sum::<usize>()
+    }
+
+    pub fn intervals(&self) -> Vec<usize> {
+        self.series_bytes.keys().cloned().collect()
+    }
+
+    pub fn last_update(&self) -> u64 {
+        self.last_update
+    }
+
+    pub fn set_series_bytes(&mut self, interval: usize, bytes: usize) {
+        self.series_bytes.insert(interval, bytes);
+    }
+
+    pub fn set_other_bytes(&mut self, bytes: usize) {
+        self.other_bytes = bytes;
+    }
+
+    pub fn set_last_update(&mut self, last_update: u64) {
+        self.last_update = last_update;
+    }
+
+    pub fn to_json(&self, stock_name: &str) -> String {
+        let mut intervals:Vec<usize> = self.intervals();
+        intervals.sort_unstable();
+
+        let series:Vec<String> = intervals.iter()
+            .map(|interval| format!("\"{}\":{}", interval, self.series_bytes[interval]))
+            .collect();
+
+        format!("{{\"stats\":\"memory\",\"sn\":{},\"bytes\":{},\"last_update\":{},\"series\":{{{}}}}}",
+            json_string(stock_name), self.bytes(), self.last_update, series.join(","))
+    }
+}
\ No newline at end of file
@@ -0,0 +1,131 @@
+use std::mem;
+use std::sync::Arc;
+use std::collections::VecDeque;
+
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
+
+use crate::metrics::latency_histogram::unix_micros;
+use crate::protocol::wire_format::Protocol;
+
+// An update encoded once and shared by reference count between every queue it is pushed to
+pub type SharedUpdate = Utf8Bytes;
+
+// An update as json and as the frame in the client's protocol, with the unix micros it was
+// queued at, and received at for live updates from producers
+#[derive(Clone)]
+pub struct QueuedUpdate {
+    pub update: SharedUpdate,
+    pub message: Message,
+    pub received_at: Option<u64>,
+    pub queued_at: u64,
+}
+
+impl QueuedUpdate {
+    fn new(update: SharedUpdate, message: Message, received_at: Option<u64>) -> Self {
+        QueuedUpdate { update, message, received_at, queued_at: unix_micros() }
+    }
+}
+
+// Updates waiting to be sent to one client. Pushing wakes the client's task up.
+// Live updates are capped at max_size, 0 leaves them unbounded. Replies and snapshots wait in a queue
+// of their own, they are sent first and never dropped
+pub struct ConnectionQueue {
+    replies: Vec<QueuedUpdate>,
+    updates: VecDeque<QueuedUpdate>,
+    notify: Arc<Notify>,
+    protocol: Protocol,
+    max_size: usize,
+    disconnect_on_overflow: bool,
+    overflowed: bool,
+    dropped: usize,
+}
+
+impl ConnectionQueue {
+    pub fn new(protocol: Protocol, max_size: usize, disconnect_on_overflow: bool) -> Self {
+        ConnectionQueue {
+            replies: Vec::new(),
+            updates: VecDeque::new(),
+            notify: Arc::new(Notify::new()),
+            protocol,
+            max_size,
+            disconnect_on_overflow,
+            overflowed: false,
+            dropped: 0,
+        }
+    }
+
+    // Live updates come encoded already, they are encoded once for every client of a protocol.
+    // Too many live updates drop the oldest one, or all of them if the client is to be disconnected.
+    // Returns the number of dropped updates
+    pub fn push(&mut self, update: SharedUpdate, message: Message, received_at: u64) -> usize {
+        let dropped:usize = match (self.overflowed, self.max_size > 0 && self.updates.len() >= self.max_size) {
+            (true, _) => 1,
+            (false, true) if self.disconnect_on_overflow => {
+                self.overflowed = true;
+
+                self.updates.drain(..).count() + 1
+            },
+            (false, true) => {
+                self.updates.pop_front();
+
+                1
+            },
+            (false, false) => 0,
+        };
+
+        if !self.overflowed {
+            self.updates.push_back(QueuedUpdate::new(update, message, Some(received_at)));
+        }
+
+        self.dropped += dropped;
+        self.notify.notify_one();
+
+        dropped
+    }
+
+    pub fn extend(&mut self, replies: Vec<String>) {
+        let replies:Vec<QueuedUpdate> = replies.into_iter().map(|v| self.encode(v)).collect();
+
+        self.replies.extend(replies);
+        self.notify.notify_one();
+    }
+
+    // Live updates queued for the previous subscription are replaced by the snapshot of the new one
+    pub fn replace(&mut self, snapshot: Vec<String>) {
+        self.updates.clear();
+        self.extend(snapshot);
+    }
+
+    fn encode(&self, json_data: String) -> QueuedUpdate {
+        let message:Message = self.protocol.encode(&json_data);
+
+        QueuedUpdate::new(SharedUpdate::from(json_data), message, None)
+    }
+
+    // Replies first, then live updates. None once the queue overflowed and the client has to be disconnected
+    pub fn take(&mut self) -> Option<Vec<QueuedUpdate>> {
+        if self.overflowed {
+            return None;
+        }
+
+        let mut updates:Vec<QueuedUpdate> = mem::take(&mut self.replies);
+        updates.extend(self.updates.drain(..));
+
+        Some(updates)
+    }
+
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    // dropped_total counts the updates dropped for every client
+    pub fn to_json(&self, dropped_total: usize) -> String {
+        format!("{{\"stats\":\"queue\",\"queued\":{},\"replies\":{},\"max_queue_size\":{},\"dropped\":{},\"dropped_total\":{}}}",
+            self.updates.len(), self.replies.len(), self.max_size, self.dropped, dropped_total)
+    }
+
+    pub fn notify(&self) -> Arc<Notify> {
+        self.notify.clone()
+    }
+}
\ No newline at end of file
@@ -0,0 +1,237 @@
+use std::thread;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::collections::{HashSet, HashMap};
+
+use tokio::sync::Notify;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::metrics::client_latency::ClientLatency;
+use crate::protocol::wire_format::Protocol;
+use crate::websockets::connection_queue::{ConnectionQueue, QueuedUpdate, SharedUpdate};
+
+// Everything the datastore keeps about one output client
+pub struct ClientConnection {
+    id: usize,
+    peer_address: SocketAddr,
+    protocol: Protocol,
+    subscription: Option<String>,
+    queue: ConnectionQueue,
+    latency: ClientLatency,
+}
+
+impl ClientConnection {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn peer_address(&self) -> SocketAddr {
+        self.peer_address
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub fn latency(&self) -> &ClientLatency {
+        &self.latency
+    }
+}
+
+struct RegistryState {
+    clients: HashMap<usize, ClientConnection>,
+    subscribers: HashMap<String, HashSet<usize>>,
+}
+
+// Owns every connected client together with the subscriber index, both only change under one lock.
+// Clients that fall more than max_queue_size updates behind lose the oldest ones or are disconnected
+pub struct ConnectionRegistry {
+    state: RwLock<RegistryState>,
+    next_id: AtomicUsize,
+    max_queue_size: usize,
+    disconnect_on_overflow: bool,
+    dropped_updates: AtomicUsize,
+}
+
+impl ConnectionRegistry {
+    pub fn new(max_queue_size: usize, disconnect_on_overflow: bool) -> Self {
+        ConnectionRegistry {
+            state: RwLock::new(RegistryState { clients: HashMap::new(), subscribers: HashMap::new() }),
+            next_id: AtomicUsize::new(0),
+            max_queue_size,
+            disconnect_on_overflow,
+            dropped_updates: AtomicUsize::new(0),
+        }
+    }
+
+    // Returns the id of the client and what wakes its task up when updates are queued
+    pub fn register(&self, peer_address: SocketAddr, protocol: Protocol) -> (usize, Arc<Notify>) {
+        let id:usize = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let queue = ConnectionQueue::new(protocol, self.max_queue_size, self.disconnect_on_overflow);
+        let notify = queue.notify();
+
+        self.state.write().unwrap().clients.insert(id, ClientConnection {
+            id,
+            peer_address,
+            protocol,
+            subscription: None,
+            queue,
+            latency: ClientLatency::new(),
+        });
+
+        (id, notify)
+    }
+
+    // Drops the client and its subscription at once. Returns it unless it was already removed
+    pub fn remove(&self, id: usize) -> Option<ClientConnection> {
+        let mut state = self.state.write().unwrap();
+        let client = state.clients.remove(&id)?;
+
+        if let Some(stock_name) = client.subscription.as_ref() {
+            remove_subscriber(&mut state.subscribers, stock_name, id);
+        }
+
+        Some(client)
+    }
+
+    pub fn unsubscribe(&self, id: usize) -> bool {
+        let mut state = self.state.write().unwrap();
+        let state = &mut *state;
+
+        let client = match state.clients.get_mut(&id) {
+            Some(v) => v,
+            None => return false,
+        };
+
+        if let Some(stock_name) = client.subscription.take() {
+            remove_subscriber(&mut state.subscribers, &stock_name, id);
+        }
+
+        true
+    }
+
+    // Replaces the subscription of a client. A snapshot replaces whatever is still queued,
+    // so it can't interleave with updates published before the subscription
+    pub fn subscribe(&self, id: usize, stock_name: &str, snapshot: Option<Vec<String>>) -> bool {
+        let mut state = self.state.write().unwrap();
+        let state = &mut *state;
+
+        let client = match state.clients.get_mut(&id) {
+            Some(v) => v,
+            None => return false,
+        };
+
+        if let Some(old_stock) = client.subscription.replace(stock_name.to_string()) {
+            remove_subscriber(&mut state.subscribers, &old_stock, id);
+        }
+
+        if let Some(snapshot) = snapshot {
+            client.queue.replace(snapshot);
+        }
+
+        state.subscribers.entry(stock_name.to_string()).or_default().insert(id);
+
+        true
+    }
+
+    // Returns the bounded buffer live updates are distributed from. Distribution runs on a thread of its own,
+    // so ingest workers only hand updates over and never wait for the registry lock
+    pub fn start_distribution(self: &Arc<Self>, buffer_size: usize) -> SyncSender<(String, SharedUpdate, u64)> {
+        let (sender, receiver) = mpsc::sync_channel::<(String, SharedUpdate, u64)>(buffer_size);
+        let connection_registry = Arc::clone(self);
+
+        thread::spawn(move || {
+            while let Ok((stock_name, update, received_at)) = receiver.recv() {
+                connection_registry.publish(&stock_name, update, received_at);
+            }
+
+            println!("Closing client distribution");
+        });
+
+        sender
+    }
+
+    // Queues a live update for the subscribers of the stock and of "*", encoded once per protocol
+    pub fn publish(&self, stock_name: &str, update: SharedUpdate, received_at: u64) {
+        let mut state = self.state.write().unwrap();
+        let state = &mut *state;
+
+        let mut ids_to_update:HashSet<usize> = HashSet::new();
+
+        for key in [stock_name, "*"] {
+            if let Some(list_of_ids) = state.subscribers.get(key) {
+                ids_to_update.extend(list_of_ids.iter());
+            }
+        }
+
+        let mut encoded:Vec<(Protocol, Message)> = Vec::new();
+        let mut dropped:usize = 0;
+
+        for id in ids_to_update.iter() {
+            let client = match state.clients.get_mut(id) {
+                Some(v) => v,
+                None => continue,
+            };
+
+            let message:Message = match encoded.iter().find(|(protocol, _)| *protocol == client.protocol) {
+                Some((_, message)) => message.clone(),
+                None => {
+                    let message:Message = client.protocol.encode(update.as_str());
+                    encoded.push((client.protocol, message.clone()));
+
+                    message
+                },
+            };
+
+            let overflowed:bool = client.queue.overflowed();
+            dropped += client.queue.push(update.clone(), message, received_at);
+
+            if !overflowed && client.queue.overflowed() {
+                println!("Queue of websocket {} overflowed, disconnecting {}", client.id, client.peer_address);
+            }
+        }
+
+        if dropped > 0 {
+            self.dropped_updates.fetch_add(dropped, Ordering::Relaxed);
+        }
+    }
+
+    // Queues replies meant for this client only
+    pub fn reply(&self, id: usize, replies: Vec<String>) -> bool {
+        match self.state.write().unwrap().clients.get_mut(&id) {
+            Some(v) => { v.queue.extend(replies); true },
+            None => false,
+        }
+    }
+
+    pub fn take(&self, id: usize) -> Option<Vec<QueuedUpdate>> {
+        self.state.write().unwrap().clients.get_mut(&id).and_then(|v| v.queue.take())
+    }
+
+    pub fn record_latency(&self, id: usize, updates: &[QueuedUpdate], sent_at: u64) {
+        if let Some(v) = self.state.write().unwrap().clients.get_mut(&id) {
+            v.latency.record(updates, sent_at);
+        }
+    }
+
+    pub fn latency_stats(&self, id: usize) -> Option<String> {
+        self.state.read().unwrap().clients.get(&id).map(|v| v.latency.to_json())
+    }
+
+    pub fn queue_stats(&self, id: usize) -> Option<String> {
+        self.state.read().unwrap().clients.get(&id).map(|v| v.queue.to_json(self.dropped_updates.load(Ordering::Relaxed)))
+    }
+}
+
+fn remove_subscriber(subscribers: &mut HashMap<String, HashSet<usize>>, stock_name: &str, id: usize) {
+    let now_empty:bool = match subscribers.get_mut(stock_name) {
+        Some(v) => { v.remove(&id); v.is_empty() },
+        None => false,
+    };
+
+    if now_empty {
+        subscribers.remove(stock_name);
+    }
+}
\ No newline at end of file
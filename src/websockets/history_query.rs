@@ -0,0 +1,69 @@
+use crate::persistence::history_archive::HistoryArchive;
+use crate::protocol::json_codec::json_string;
+use crate::value_store::stock_information_cache::StockInformationCache;
+
+// {"history":"AAPL","si":60,"from":T1,"to":T2}
+pub struct HistoryQuery {
+    stock_name: String,
+    interval: usize,
+    from: i64,
+    to: i64,
+}
+
+impl HistoryQuery {
+    pub fn new(stock_name: String, interval: usize, from: i64, to: i64) -> Self {
+        HistoryQuery { stock_name, interval, from, to }
+    }
+
+    // Bars still held in memory are served from the cache, older ones from the history archive.
+    // Only the oldest max_bars bars of the range are sent, the chunks are marked truncated if there are more
+    pub fn execute(&self,
+                   stock_information_cache: &StockInformationCache,
+                   history_archive: &HistoryArchive,
+                   chunk_size: usize,
+                   max_bars: usize) -> Vec<String> {
+        let cache_view = stock_information_cache.view();
+
+        let oldest_timestamp:Option<i64> = cache_view.get_oldest_timestamp(&self.stock_name, self.interval);
+        let memory_bars = cache_view.get_history_range(&self.stock_name, self.interval, self.from, self.to);
+
+        let mut bars:Vec<String> = Vec::new();
+
+        let archive_to:Option<i64> = match oldest_timestamp {
+            Some(v) if v <= self.from => None,
+            Some(v) => Some(self.to.min(v - 1)),
+            None => Some(self.to),
+        };
+
+        if let Some(to) = archive_to {
+            match history_archive.read_range(&self.stock_name, self.interval, self.from, to, max_bars.saturating_add(1)) {
+                Ok(v) => bars.extend(v.into_iter().map(|(_, json_data)| json_data)),
+                Err(e) => return vec![self.error_message(&format!("Couldn't read history archive: {}", e))],
+            }
+        }
+
+        bars.extend(memory_bars.into_iter().map(|(_, json_data)| json_data));
+
+        let truncated:bool = bars.len() > max_bars;
+        bars.truncate(max_bars);
+
+        self.to_chunks(&bars, chunk_size.max(1), truncated)
+    }
+
+    pub fn error_message(&self, error: &str) -> String {
+        format!("{{\"history\":{},\"si\":{},\"error\":{}}}", json_string(&self.stock_name), self.interval, json_string(error))
+    }
+
+    fn to_chunks(&self, bars: &[String], chunk_size: usize, truncated: bool) -> Vec<String> {
+        let num_of_chunks:usize = bars.len().div_ceil(chunk_size).max(1);
+
+        (0..num_of_chunks)
+            .map(|chunk| {
+                let chunk_bars:&[String] = &bars[(chunk * chunk_size).min(bars.len())..((chunk + 1) * chunk_size).min(bars.len())];
+
+                format!("{{\"history\":{},\"si\":{},\"chunk\":{},\"last\":{},\"truncated\":{},\"bars\":[{}]}}",
+                    json_string(&self.stock_name), self.interval, chunk, chunk + 1 == num_of_chunks, truncated, chunk_bars.join(","))
+            })
+            .collect()
+    }
+}
\ No newline at end of file
@@ -1,3 +1,6 @@
+pub mod connection_queue;
+pub mod connection_registry;
+pub mod history_query;
 pub mod notification_server_in;
 pub mod notification_server_out;
 pub mod websocket_server;
\ No newline at end of file
@@ -1,100 +1,104 @@
-use std::sync::{Arc, RwLock};
-use std::collections::{HashSet, HashMap};
-use std::net::TcpListener;
+use std::sync::Arc;
 
-use tungstenite::{
-    Message,
-    accept,
-};
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
 
-use crate::value_store::stock_information_cache::StockInformationCache;
+use crate::ingest::ingest_pipeline::IngestPipeline;
+use crate::metrics::latency_histogram::unix_micros;
+use crate::protocol::feed_mapping::FeedMapping;
+use crate::protocol::json_codec::{decode_binary_stock_info, decode_stock_info, error_message};
+use crate::protocol::polygon_codec;
+use crate::protocol::wire_format::{Feed, accept_with_protocol};
+use crate::value_store::stock_information_cache::{StockInformation, StockInformationCache};
 
 pub struct NotificationServerIn {
     ip_server: String,
-    connection_queue: Arc<RwLock<HashMap::<usize, Vec<String>>>>,
-    subscriber_map: Arc<RwLock<HashMap::<String, HashSet<usize>>>>,
-    stock_information_cache: Arc<RwLock<StockInformationCache>>,
+    stock_information_cache: Arc<StockInformationCache>,
+    ingest_pipeline: Arc<IngestPipeline>,
+    feed_mappings: Arc<Vec<FeedMapping>>,
 }
 
 impl NotificationServerIn {
     pub fn new(ip_server: String,
-               connection_queue: Arc<RwLock<HashMap::<usize, Vec<String>>>>,
-               subscriber_map: Arc<RwLock<HashMap::<String, HashSet<usize>>>>,
-               stock_information_cache: Arc<RwLock<StockInformationCache>>) -> Self {
+               stock_information_cache: Arc<StockInformationCache>,
+               ingest_pipeline: Arc<IngestPipeline>,
+               feed_mappings: Arc<Vec<FeedMapping>>) -> Self {
         NotificationServerIn {
-            ip_server: ip_server, 
-            connection_queue: connection_queue, 
-            subscriber_map: subscriber_map,
-            stock_information_cache: stock_information_cache,
+            ip_server,
+            stock_information_cache,
+            ingest_pipeline,
+            feed_mappings,
         }
     }
 
-    pub fn start_server(&mut self) {
-        let server = TcpListener::bind(self.ip_server.clone()).unwrap();
+    pub async fn start_server(&self) {
+        let server = TcpListener::bind(self.ip_server.clone()).await.unwrap();
 
-        for stream in server.incoming() {
-            let stream = match stream {
-                Ok(v) => v,
+        loop {
+            let stream = match server.accept().await {
+                Ok((v, _)) => v,
                 Err(_) => continue,
             };
 
-            let mut websocket = match accept(stream) {
-                Ok(v) => v,
-                Err(_) => continue,
-            };
+            tokio::spawn(handle_producer(
+                stream,
+                self.stock_information_cache.clone(),
+                self.ingest_pipeline.clone(),
+                self.feed_mappings.clone()
+            ));
+        }
+    }
+}
+
+async fn handle_producer(stream: TcpStream,
+                         stock_information_cache: Arc<StockInformationCache>,
+                         ingest_pipeline: Arc<IngestPipeline>,
+                         feed_mappings: Arc<Vec<FeedMapping>>) {
+    let (mut websocket, protocol) = match accept_with_protocol(stream, Some(&feed_mappings)).await {
+        Some(v) => v,
+        None => return,
+    };
+
+    let _ = websocket.send(Message::Text(stock_information_cache.get_stock_names().into())).await;
+
+    'messages: while let Some(message) = websocket.next().await {
+        let message = match message {
+            Ok(p) => p,
+            Err(e) => {
+                println!("Error receiving message {} \n Closing Client", e);
+
+                break;
+            },
+        };
+
+        let received_at:u64 = unix_micros();
+
+        // Malformed updates are rejected here, before they reach the log or the cache.
+        // Updates are kept as json with version 1 keys from then on. A feed message may hold several
+        let updates:Vec<Result<(StockInformation, String), String>> = match (message, protocol.feed) {
+            (Message::Text(v), Feed::Polygon) => polygon_codec::decode_aggregates(&v),
+            (Message::Text(v), Feed::Mapped(index)) => feed_mappings[index].decode(&v),
+            (Message::Text(v), Feed::Datastore) => vec![protocol.incoming(&v)
+                .and_then(|json_data| decode_stock_info(&json_data).map(|stock_info| (stock_info, json_data)))],
+            (Message::Binary(v), _) => vec![decode_binary_stock_info(protocol, &v).map(|stock_info| {
+                let json_data:String = stock_info.to_json();
+
+                (stock_info, json_data)
+            })],
+            _ => continue,
+        };
+
+        for update in updates.into_iter() {
+            match update {
+                Ok((stock_info, json_data)) => ingest_pipeline.submit(stock_info, json_data, received_at).await,
+                Err(e) => {
+                    println!("Rejected update: {}", e);
 
-            let _ = websocket.send(Message::Text(self.stock_information_cache.read().unwrap().get_stock_names()));
-
-            loop {
-                let message = match websocket.read() {
-                    Ok(p) => p,
-                    Err(e) => {
-                        println!("Error receiving message {} \n Closing Client", e);
-                        
-                        break;
-                    },
-                };
-
-                match message {
-                    msg @ Message::Text(_) => {
-                        let text: String = match msg.into_text() {
-                            Ok(v) => v,
-                            Err(_) => continue,
-                        };
-
-                        let name = self.stock_information_cache.write().unwrap().add_json(&text);
-    
-                        let mut ids_to_update:HashSet<usize> = HashSet::new();
-    
-                        match self.subscriber_map.read().unwrap().get(&name){
-                            Some(list_of_ids) => {
-                                for id in list_of_ids.iter() {
-                                    ids_to_update.insert(*id);
-                                }
-                            },
-                            None => (),
-                        }
-                        
-                        match self.subscriber_map.read().unwrap().get("*") {
-                            Some(list_of_ids) => {
-                                for id in list_of_ids.iter() {
-                                    ids_to_update.insert(*id);
-                                }
-                            },
-                            None => (),
-                        }
-    
-                        let mut connection_vec = self.connection_queue.write().unwrap();
-    
-                        for id in ids_to_update.iter() {
-                            match connection_vec.get_mut(id) {
-                                Some(v) => v.push(text.clone()),
-                                None => continue,
-                            };
-                        }
+                    if websocket.send(protocol.encode(&error_message(&e))).await.is_err() {
+                        break 'messages;
                     }
-                    _ => (),
-                }
+                },
             }
         }
     }
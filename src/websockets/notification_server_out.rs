@@ -1,244 +1,294 @@
-use std::thread;
-use std::sync::{Arc, RwLock};
+use std::net::SocketAddr;
+use std::sync::Arc;
 use std::time::Duration;
-use std::collections::{HashSet, HashMap};
-use std::net::{TcpStream, TcpListener};
 
-use tungstenite::{
-    accept,
-    protocol::{Role, WebSocket},
-    Message,
+use futures_util::{
+    stream::SplitSink,
+    SinkExt, StreamExt,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{
+    tungstenite::Message,
+    WebSocketStream,
 };
 
+use crate::ingest::csv_loader::CsvLoader;
+use crate::metrics::ingest_metrics::IngestMetrics;
+use crate::metrics::latency_histogram::unix_micros;
+use crate::persistence::history_archive::HistoryArchive;
+use crate::persistence::history_export::HistoryExporter;
+use crate::protocol::delta_encoder::DeltaEncoder;
+use crate::protocol::json_codec::{ClientCommand, error_message, json_string};
+use crate::protocol::wire_format::{Protocol, accept_with_protocol};
+use crate::value_store::cache_shard::append_json_fields;
 use crate::value_store::stock_information_cache::StockInformationCache;
+use crate::websockets::connection_queue::QueuedUpdate;
+use crate::websockets::connection_registry::ConnectionRegistry;
+
+const PING_INTERVAL: Duration = Duration::from_secs(1);
+
+// How every client is served, from the datastore config
+#[derive(Clone, Copy)]
+pub struct ClientSettings {
+    pub history_chunk_size: usize,
+    pub history_max_bars: usize,
+    pub latency_stamps: bool,
+    pub delta_keyframe_interval: usize,
+    pub admin_commands: bool,
+}
+
+// What every client task shares
+#[derive(Clone)]
+pub struct ClientServices {
+    pub connection_registry: Arc<ConnectionRegistry>,
+    pub ingest_metrics: Arc<IngestMetrics>,
+    pub stock_information_cache: Arc<StockInformationCache>,
+    pub history_archive: Arc<HistoryArchive>,
+    pub csv_loader: Arc<CsvLoader>,
+    pub history_exporter: Arc<HistoryExporter>,
+}
 
 pub struct NotificationServerOut {
     ip_server: String,
-    connection_queue: Arc<RwLock<HashMap::<usize, Vec<String>>>>,
-    subscriber_map: Arc<RwLock<HashMap::<String, HashSet<usize>>>>,
-    stock_information_cache: Arc<RwLock<StockInformationCache>>,
+    client_services: ClientServices,
+    client_settings: ClientSettings,
 }
 
 impl NotificationServerOut {
-    pub fn new(ip_server: String,
-               connection_queue: Arc<RwLock<HashMap::<usize, Vec<String>>>>,
-               subscriber_map: Arc<RwLock<HashMap::<String, HashSet<usize>>>>,
-               stock_information_cache: Arc<RwLock<StockInformationCache>>) -> Self {
-        NotificationServerOut { 
-            ip_server: ip_server,
-            connection_queue: connection_queue,
-            subscriber_map: subscriber_map,
-            stock_information_cache: stock_information_cache,
+    pub fn new(ip_server: String, client_services: ClientServices, client_settings: ClientSettings) -> Self {
+        NotificationServerOut {
+            ip_server,
+            client_services,
+            client_settings,
         }
     }
 
-    pub fn start_server(&self) {
-        let server = TcpListener::bind(self.ip_server.clone()).unwrap();
-
-        let connection_queue = self.connection_queue.clone();
-        let subscriber_map = self.subscriber_map.clone();
-        let stock_information_cache = self.stock_information_cache.clone();
-
-        thread::spawn(move || {
-            let mut id:usize = 0;
-
-            for stream in server.incoming() {
-                let id_cloned = id;
-                let connection_queue_cloned = connection_queue.clone();
-                let subscriber_map_cloned = subscriber_map.clone();
-                let stock_information_cache_cloned = stock_information_cache.clone();
-
-                thread::spawn(move || {
-                    let stream_read = stream.unwrap();
-                    let send_stream = stream_read.try_clone().unwrap();
-
-                    let websocket_read = match accept(stream_read) {
-                        Ok(v) => v,
-                        Err(e) => return,
-                    };
-                    
-                    let websocket_send = WebSocket::from_raw_socket(send_stream, Role::Server, None);
-        
-                    connection_queue_cloned.write().unwrap().insert(id, Vec::new());
-                    
-                    start_websocket_receiver(
-                        websocket_read, connection_queue_cloned.clone(), 
-                        subscriber_map_cloned, stock_information_cache_cloned, 
-                        id_cloned
-                    );
-                    
-                    start_websocket_sender(
-                        websocket_send, 
-                        connection_queue_cloned, 
-                        id_cloned
-                    );
-        
-                    println!("Spawned websocket {}", id_cloned);
-                });
-
-                id += 1;
-            }
-        });
-    }
-}
-
-fn start_websocket_receiver(mut receiver: WebSocket<TcpStream>,
-                            connection_queue: Arc<RwLock<HashMap::<usize, Vec<String>>>>,
-                            subscriber_map: Arc<RwLock<HashMap::<String, HashSet<usize>>>>,
-                            stock_information_cache: Arc<RwLock<StockInformationCache>>,
-                            id: usize) {
-    thread::spawn(move || {
-        let mut old_stock:String = String::new();
+    pub async fn start_server(&self) {
+        let server = TcpListener::bind(self.ip_server.clone()).await.unwrap();
 
         loop {
-            let message_json:String = match receiver.read() {
-                Ok(message) => match message {
-                    msg @ Message::Text(_) => msg.into_text().unwrap(),
-                    _msg @ Message::Ping(_) | _msg @ Message::Pong(_) => continue,
-                    _ => break,
-                },
-                Err(e) =>{
-                    println!("Error in message {} thread: {}", e, id);
-
-                    break;
-                },
+            let (stream, peer_address) = match server.accept().await {
+                Ok(v) => v,
+                Err(_) => continue,
             };
 
-            let parsed_json:HashMap<String,String> = parse_json(&message_json);
-
-            if !parsed_json.contains_key("stock") {
-                println!("Error with stock in thread {}", id);
-
-                continue;
-            }
-
-            match subscriber_map.write().unwrap().get_mut(&old_stock) {
-                Some(v) => { v.remove(&id); },
-                None => println!("Couldn't find key {:?}", &old_stock),
-            };
-
-            let stock_name:String = parsed_json.get("stock").unwrap().to_string();
-
-            println!("Received Stockname {}", stock_name);
-
-            if &stock_name[..] != "*" && !stock_information_cache.read().unwrap().has_key(&stock_name) {
-                println!("Couldn't find key {:?}", old_stock);
-
-                continue;
-            }
-
-            let key_is_there = subscriber_map.read().unwrap().contains_key(&stock_name);
-
-            match key_is_there {
-                true => { subscriber_map.write().unwrap().get_mut(&stock_name).unwrap().insert(id); },
-                false => { subscriber_map.write().unwrap().insert(stock_name.clone(), HashSet::from([id])); },
-            };
-            
-            if &stock_name[..] == "*" {
-                connection_queue.write().unwrap().insert(id, stock_information_cache.read().unwrap().get_entire_cache());
-            }
-
-            old_stock = stock_name;
+            tokio::spawn(handle_client(
+                stream,
+                peer_address,
+                self.client_services.clone(),
+                self.client_settings
+            ));
         }
+    }
+}
 
-        println!("Closing Receiver thread {}", id);
+// One task per client, it reads subscriptions and writes whatever is queued for the client
+async fn handle_client(stream: TcpStream,
+                       peer_address: SocketAddr,
+                       client_services: ClientServices,
+                       client_settings: ClientSettings) {
+    let ClientServices { connection_registry, ingest_metrics, stock_information_cache, history_archive, csv_loader, history_exporter } = client_services;
 
-        match subscriber_map.write().unwrap().get_mut(&old_stock) {
-            Some(v) => { v.remove(&id); },
-            None => (),
-        };
-    });
-}
+    let (websocket, protocol) = match accept_with_protocol(stream, None).await {
+        Some(v) => v,
+        None => return,
+    };
 
-fn start_websocket_sender(mut sender: WebSocket<TcpStream>,
-                   connection_queue: Arc<RwLock<HashMap::<usize, Vec<String>>>>,
-                   id: usize) {
-    thread::spawn(move || {
-        let mut ping_cnt:usize = 0;
+    let (mut sender, mut receiver) = websocket.split();
 
-        loop {
-            if connection_queue.read().unwrap().len() > 1000 { 
-                continue; 
-            }
+    let (id, notify) = connection_registry.register(peer_address, protocol);
 
-            let connection_vec = match connection_queue.read().unwrap().get(&id) {
-                Some(v) => v.clone(),
-                None => break,
-            };
+    println!("Spawned websocket {} for {} using {}", id, peer_address, protocol.subprotocol());
 
-            if connection_vec.len() == 0 {
-                thread::sleep(Duration::from_millis(10));
+    let mut ping_interval = tokio::time::interval(PING_INTERVAL);
+    let mut sent_since_ping:bool = false;
+    let mut delta_encoder:Option<DeltaEncoder> = None;
 
-                if !send_ping(&mut sender, &mut ping_cnt) { 
-                    break; 
-                }
-                
-                continue;
-            }
+    loop {
+        tokio::select! {
+            message = receiver.next() => {
+                let command = match message {
+                    Some(Ok(Message::Text(v))) => protocol.incoming(&v).and_then(|json_data| ClientCommand::decode(&json_data)),
+                    Some(Ok(Message::Binary(v))) => ClientCommand::decode_binary(protocol, &v),
+                    Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => continue,
+                    Some(Ok(_)) | None => break,
+                    Some(Err(e)) => {
+                        println!("Error in message {} task: {}", e, id);
 
-            match connection_queue.write().unwrap().get_mut(&id) {
-                Some(v) => v.clear(),
-                None => break,
-            };
+                        break;
+                    },
+                };
+
+                let command:Result<ClientCommand, String> = match command {
+                    Ok(v) if v.is_admin() && !client_settings.admin_commands => Err("Command is only accepted on the admin port".to_string()),
+                    v => v,
+                };
 
-            for update in connection_vec.into_iter() {
-                match sender.send(Message::Text(update)) {
+                let command:ClientCommand = match command {
                     Ok(v) => v,
-                    Err(_) => break,
+                    Err(e) => {
+                        println!("Rejected command in task {}: {}", id, e);
+
+                        if !connection_registry.reply(id, vec![error_message(&e)]) {
+                            break;
+                        }
+
+                        continue;
+                    },
+                };
+
+                match command {
+                    ClientCommand::Stats { stats, stock_name } => {
+                        let reply:String = match (stats.as_str(), stock_name) {
+                            ("latency", _) => connection_registry.latency_stats(id).unwrap_or_default(),
+                            ("queue", _) => connection_registry.queue_stats(id).unwrap_or_default(),
+                            ("ingest", _) => ingest_metrics.to_json(),
+                            ("memory", Some(stock_name)) => stock_information_cache.symbol_memory_stats(&stock_name),
+                            ("memory", None) => stock_information_cache.memory_stats(),
+                            (v, _) => format!("{{\"stats\":{},\"error\":\"Unknown stats\"}}", json_string(v)),
+                        };
+
+                        if !connection_registry.reply(id, vec![reply]) {
+                            break;
+                        }
+                    },
+                    ClientCommand::History(query) => {
+                        let stock_information_cache = stock_information_cache.clone();
+                        let history_archive = history_archive.clone();
+
+                        // Reading the history archive blocks, keep it off the runtime threads. A panic there
+                        // ends the query, not the client
+                        let replies:Vec<String> = match tokio::task::spawn_blocking(move || {
+                            query.execute(&stock_information_cache, &history_archive, client_settings.history_chunk_size, client_settings.history_max_bars)
+                        }).await {
+                            Ok(v) => v,
+                            Err(e) => vec![error_message(&format!("History query failed: {}", e))],
+                        };
+
+                        if !connection_registry.reply(id, replies) {
+                            break;
+                        }
+                    },
+                    ClientCommand::LoadCsv(file) => {
+                        let csv_loader = csv_loader.clone();
+                        let file_name:String = file.clone();
+
+                        let reply:String = match tokio::task::spawn_blocking(move || csv_loader.load_client_file(&file_name)).await {
+                            Ok(Ok(report)) => report.to_json(&file),
+                            Ok(Err(e)) => format!("{{\"stats\":\"load_csv\",\"file\":{},\"error\":{}}}", json_string(&file), json_string(&e)),
+                            Err(e) => format!("{{\"stats\":\"load_csv\",\"file\":{},\"error\":{}}}", json_string(&file), json_string(&e.to_string())),
+                        };
+
+                        if !connection_registry.reply(id, vec![reply]) {
+                            break;
+                        }
+                    },
+                    ClientCommand::ExportHistory { file, filter } => {
+                        let history_exporter = history_exporter.clone();
+                        let file_name:String = file.clone();
+
+                        let reply:String = match tokio::task::spawn_blocking(move || history_exporter.export_client_file(&file_name, &filter)).await {
+                            Ok(Ok(exported)) => format!("{{\"stats\":\"export_history\",\"file\":{},\"exported\":{}}}", json_string(&file), exported),
+                            Ok(Err(e)) => format!("{{\"stats\":\"export_history\",\"file\":{},\"error\":{}}}", json_string(&file), json_string(&e)),
+                            Err(e) => format!("{{\"stats\":\"export_history\",\"file\":{},\"error\":{}}}", json_string(&file), json_string(&e.to_string())),
+                        };
+
+                        if !connection_registry.reply(id, vec![reply]) {
+                            break;
+                        }
+                    },
+                    ClientCommand::Delta(enabled) => {
+                        delta_encoder = match enabled {
+                            true => Some(DeltaEncoder::new(client_settings.delta_keyframe_interval)),
+                            false => None,
+                        };
+                    },
+                    ClientCommand::Subscribe(stock_name) => {
+                        connection_registry.unsubscribe(id);
+
+                        println!("Received Stockname {}", stock_name);
+
+                        if &stock_name[..] != "*" && !stock_information_cache.has_key(&stock_name) {
+                            println!("Couldn't find key {:?}", stock_name);
+
+                            continue;
+                        }
+
+                        let snapshot:Option<Vec<String>> = match &stock_name[..] {
+                            "*" => Some(stock_information_cache.get_entire_cache()),
+                            _ => None,
+                        };
+
+                        if !connection_registry.subscribe(id, &stock_name, snapshot) {
+                            break;
+                        }
+
+                        // Deltas are against what was sent before the subscription, the snapshot replaced it
+                        if let Some(encoder) = delta_encoder.as_mut() {
+                            encoder.reset();
+                        }
+                    },
                 }
-            }
-        }
+            },
+            _ = notify.notified() => {
+                let updates:Vec<QueuedUpdate> = match connection_registry.take(id) {
+                    Some(v) => v,
+                    None => break,
+                };
 
-        println!("Error sending message. Closing Websocket {}", id);
-        connection_queue.write().unwrap().remove(&id);
-    });
+                if !send_updates(&mut sender, &updates, protocol, client_settings.latency_stamps, delta_encoder.as_mut()).await {
+                    println!("Error sending message. Closing Websocket {}", id);
 
-}
+                    break;
+                }
 
-fn send_ping(sender: &mut WebSocket<TcpStream>, ping_cnt: &mut usize) -> bool {
-    *ping_cnt += 1;
+                connection_registry.record_latency(id, &updates, unix_micros());
+                sent_since_ping = true;
+            },
+            _ = ping_interval.tick() => {
+                if !sent_since_ping && sender.send(Message::Ping(Vec::new().into())).await.is_err() {
+                    break;
+                }
 
-    if *ping_cnt < 100 { 
-        return true; 
+                sent_since_ping = false;
+            },
+        }
     }
 
-    match sender.send(Message::Ping(Vec::new())) {
-        Ok(v) => v,
-        Err(_) =>  return false,
-    };
+    match connection_registry.remove(id) {
+        Some(client) => println!("Closing Websocket {} for {} using {} latency {}",
+            client.id(), client.peer_address(), client.protocol().subprotocol(), client.latency().to_json()),
+        None => println!("Closing Websocket {}", id),
+    }
+}
 
-    *ping_cnt = 0;
+// With latency stamps every live update gets the unix micros it was written at as "wt", and in delta
+// mode only the fields that changed are sent. Both mean encoding the update for this client alone
+async fn send_updates(sender: &mut SplitSink<WebSocketStream<TcpStream>, Message>,
+                      updates: &[QueuedUpdate],
+                      protocol: Protocol,
+                      latency_stamps: bool,
+                      mut delta_encoder: Option<&mut DeltaEncoder>) -> bool {
+    for queued in updates.iter() {
+        let json_data:Option<String> = match (latency_stamps, queued.received_at) {
+            (true, Some(_)) => Some(append_json_fields(queued.update.as_str(), &format!("\"wt\":{}", unix_micros()))),
+            _ => None,
+        };
 
-    true
-}
+        let delta:Option<String> = match (delta_encoder.as_mut(), queued.received_at) {
+            (Some(encoder), Some(_)) => encoder.encode(json_data.as_deref().unwrap_or(queued.update.as_str())),
+            _ => None,
+        };
 
-pub fn parse_json(json_data: &str) -> HashMap<String ,String> {
-    let mut tmp: String = String::new();
-    let mut key: String = String::new();
-
-    let mut parsed_json:HashMap<String,String> = HashMap::new();
-
-    for p in json_data.chars() {
-        if p == ' ' || p == '\n' || p == '\t' || p == '\"' || p == '{' || p == '}' { continue; }
-        
-        if p == ':' || p == ',' {
-            match key.len() {
-                0 => key = tmp,
-                _ => {
-                    parsed_json.insert(key, tmp);
-                    key = String::new();
-                }
-            };
-            
-            tmp = String::new();
+        let message:Message = match (delta, json_data) {
+            (Some(v), _) | (None, Some(v)) => protocol.encode(&v),
+            (None, None) => queued.message.clone(),
+        };
 
-            continue;
+        if sender.feed(message).await.is_err() {
+            return false;
         }
-
-        tmp.push(p);
     }
 
-    if key.len() > 0 && tmp.len() > 0 { parsed_json.insert(key, tmp); } 
-
-    parsed_json
+    sender.flush().await.is_ok()
 }
\ No newline at end of file
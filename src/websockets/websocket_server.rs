@@ -1,50 +1,198 @@
-use std::sync::{Arc, RwLock};
-use std::collections::{HashSet, HashMap};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use crate::value_store::stock_information_cache::StockInformationCache;
+use crate::events::event_bus::{ChannelConsumer, EventBus, finalized_bar, live_update};
+use crate::file_reader::datastore_config_reader::DatastoreConfig;
+use crate::ingest::csv_loader::CsvLoader;
+use crate::ingest::ingest_pipeline::IngestPipeline;
+use crate::metrics::ingest_metrics::IngestMetrics;
+use crate::persistence::cache_snapshot::CacheSnapshot;
+use crate::persistence::history_archive::HistoryArchive;
+use crate::persistence::history_export::{ExportFilter, HistoryExporter};
+use crate::persistence::postgres_sink::PostgresSink;
+use crate::persistence::write_ahead_log::WriteAheadLog;
+use crate::protocol::feed_mapping::FeedMapping;
+use crate::protocol::json_codec::json_string;
+use crate::value_store::price::TickPrecision;
+use crate::value_store::stock_information_cache::{StockInformation, StockInformationCache};
+use crate::websockets::connection_registry::ConnectionRegistry;
 use crate::websockets::notification_server_in::NotificationServerIn;
-use crate::websockets::notification_server_out::NotificationServerOut;
+use crate::websockets::notification_server_out::{ClientServices, ClientSettings, NotificationServerOut};
 
 pub struct WebSocketServer {
     ip_server_in: String,
     ip_server_out: String,
     stock_list: Vec<String>,
+    config: DatastoreConfig,
+    feed_mappings: Vec<FeedMapping>,
 }
 
 impl WebSocketServer {
-    pub fn new(ip_server_in: &str, ip_server_out: &str, stock_list: Vec<String>) -> Self {
+    pub fn new(ip_server_in: &str,
+               ip_server_out: &str,
+               stock_list: Vec<String>,
+               config: DatastoreConfig,
+               feed_mappings: Vec<FeedMapping>) -> Self {
         WebSocketServer { 
             ip_server_in: ip_server_in.to_string(), 
             ip_server_out: ip_server_out.to_string(),
-            stock_list: stock_list,
+            stock_list,
+            config,
+            feed_mappings,
         }
     }
 
-    pub fn start_server(&self) {
-        let connection_queue = Arc::new(RwLock::new(HashMap::<usize, Vec<String>>::new()));
-        let stock_information_cache = Arc::new(RwLock::new(StockInformationCache::new()));
-        let subscriber_map = Arc::new(RwLock::new(HashMap::<String, HashSet<usize>>::new()));
+    pub async fn start_server(&self) {
+        let event_bus = Arc::new(EventBus::new());
+        let connection_registry = Arc::new(ConnectionRegistry::new(self.config.client_queue_size, self.config.client_queue_disconnect));
+        let ingest_metrics = Arc::new(IngestMetrics::new());
 
-        for stock_name in self.stock_list.clone().into_iter() {
-            stock_information_cache.write().unwrap().add_json(&format!("{{sn:{},si{}}}", stock_name, 1)[..]);
+        let sender = connection_registry.start_distribution(self.config.distribution_buffer_size);
+
+        event_bus.subscribe(Arc::new(ChannelConsumer::new("client distribution", sender, live_update)));
+        event_bus.subscribe(ingest_metrics.clone());
+
+        let stock_information_cache = Arc::new(StockInformationCache::new(
+            self.config.session_reset_offset,
+            self.config.cache_shards,
+            self.config.memory_budget,
+            self.stock_list.clone(),
+            TickPrecision::new(self.config.price_precision, self.config.symbol_price_precision.clone()),
+            Arc::clone(&event_bus)
+        ));
+
+        let write_ahead_log = match WriteAheadLog::open(&self.config.wal_dir, self.config.wal_segment_size, self.config.wal_fsync) {
+            Ok(v) => Arc::new(Mutex::new(v)),
+            Err(e) => panic!("Couldn't open write ahead log {}: {}", self.config.wal_dir, e),
+        };
+
+        if !self.config.postgres_url.is_empty() {
+            let postgres_sink = PostgresSink::new(
+                &self.config.postgres_url,
+                self.config.postgres_batch_size,
+                Duration::from_millis(self.config.postgres_flush_ms),
+                self.config.postgres_max_retries
+            );
+
+            let sender = postgres_sink.start(self.config.postgres_buffer_size);
+
+            event_bus.subscribe(Arc::new(ChannelConsumer::new("postgres", sender, finalized_bar)));
         }
 
-        let notification_server_out = NotificationServerOut::new(
-            self.ip_server_out.clone(),
-            Arc::clone(&connection_queue), 
-            Arc::clone(&subscriber_map), 
-            Arc::clone(&stock_information_cache)
+        let history_archive = Arc::new(HistoryArchive::new(&self.config.history_dir));
+
+        let sender = history_archive.start(self.config.history_buffer_size);
+
+        event_bus.subscribe(Arc::new(ChannelConsumer::new("history archive", sender, finalized_bar)));
+
+        let cache_snapshot = CacheSnapshot::new(
+            &self.config.snapshot_path,
+            Duration::from_secs(self.config.snapshot_interval_secs),
+            Arc::clone(&stock_information_cache),
+            Arc::clone(&write_ahead_log)
         );
+
+        cache_snapshot.restore();
+
+        for stock_name in self.stock_list.clone().into_iter() {
+            if stock_information_cache.has_key(&stock_name) {
+                continue;
+            }
+
+            let json_data:String = format!("{{\"sn\":{},\"si\":0}}", json_string(&stock_name));
+
+            stock_information_cache.add_json(StockInformation { stock_name, ..StockInformation::new() }, &json_data);
+        }
+
+        cache_snapshot.replay_log();
+
+        let csv_loader = Arc::new(CsvLoader::new(
+            &self.config.csv_columns,
+            self.config.csv_header,
+            &self.config.csv_load_dir,
+            Arc::clone(&stock_information_cache),
+            Arc::clone(&history_archive)
+        ));
+
+        for file in self.config.load_csv.iter() {
+            match csv_loader.load_file(Path::new(file)) {
+                Ok(report) => for error in report.errors.iter() { println!("Skipped {}", error); },
+                Err(e) => panic!("{}", e),
+            }
+        }
+
+        let history_exporter = Arc::new(HistoryExporter::new(&self.config.export_dir, Arc::clone(&stock_information_cache)));
+
+        // Exporting from the command line dumps the restored history and exits without opening the ports
+        if !self.config.export_history.is_empty() {
+            let filter = ExportFilter::new(
+                self.config.export_symbols.clone(),
+                self.config.export_interval,
+                self.config.export_from,
+                self.config.export_to
+            );
+
+            for file in self.config.export_history.iter() {
+                match history_exporter.export_file(Path::new(file), &filter, true) {
+                    Ok(_) => (),
+                    Err(e) => panic!("{}", e),
+                }
+            }
+
+            return;
+        }
+
+        cache_snapshot.start_snapshots();
+
+        let client_services = ClientServices {
+            connection_registry: Arc::clone(&connection_registry),
+            ingest_metrics: Arc::clone(&ingest_metrics),
+            stock_information_cache: Arc::clone(&stock_information_cache),
+            history_archive: Arc::clone(&history_archive),
+            csv_loader: Arc::clone(&csv_loader),
+            history_exporter: Arc::clone(&history_exporter),
+        };
+
+        let client_settings = ClientSettings {
+            history_chunk_size: self.config.history_chunk_size,
+            history_max_bars: self.config.history_max_bars,
+            latency_stamps: self.config.latency_stamps,
+            delta_keyframe_interval: self.config.delta_keyframe_interval,
+            admin_commands: false,
+        };
+
+        // Same as the output port, plus the commands that read and write files of the datastore
+        if !self.config.admin_address.is_empty() {
+            let admin_server = NotificationServerOut::new(
+                self.config.admin_address.clone(),
+                client_services.clone(),
+                ClientSettings { admin_commands: true, ..client_settings }
+            );
+
+            tokio::spawn(async move { admin_server.start_server().await });
+        }
+
+        let notification_server_out = NotificationServerOut::new(self.ip_server_out.clone(), client_services, client_settings);
         
-        notification_server_out.start_server();
+        tokio::spawn(async move { notification_server_out.start_server().await });
+
+        let ingest_pipeline = IngestPipeline::start(
+            self.config.ingest_workers,
+            self.config.ingest_queue_size,
+            Arc::clone(&event_bus),
+            Arc::clone(&stock_information_cache),
+            Arc::clone(&write_ahead_log),
+            self.config.latency_stamps
+        );
 
-        let mut notification_server_in = NotificationServerIn::new(
+        let notification_server_in = NotificationServerIn::new(
             self.ip_server_in.clone(),
-            Arc::clone(&connection_queue), 
-            Arc::clone(&subscriber_map), 
-            Arc::clone(&stock_information_cache)
+            Arc::clone(&stock_information_cache),
+            Arc::new(ingest_pipeline),
+            Arc::new(self.feed_mappings.clone())
         );
 
-        notification_server_in.start_server();
+        notification_server_in.start_server().await;
     }
 }
\ No newline at end of file